@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use foundry_evm::executor::Backend;
+
+use crate::evm::create_fork_opts;
+use crate::structs::{ BackendKey, BackendPool, ForkError, PooledFork, UpstreamLease, WarmFork };
+
+impl BackendPool {
+    /// Pool of at most `size` warm forks, each dropped after `ttl` without use. A size of 0
+    /// disables the pool.
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        BackendPool {
+            size,
+            ttl,
+            forks: Mutex::new(Default::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.size > 0
+    }
+
+    /// The warm fork for `key`, unless there is none or its upstream failed since.
+    pub fn checkout(&self, key: &BackendKey) -> Option<Arc<WarmFork>> {
+        let mut forks = self.forks.lock().unwrap();
+        self.prune(&mut forks);
+
+        let pooled = forks.get_mut(key)?;
+        if pooled.fork.upstream.failure().is_some() {
+            forks.remove(key);
+            return None;
+        }
+        pooled.last_used = Instant::now();
+        Some(Arc::clone(&pooled.fork))
+    }
+
+    /// Adds a warm fork, dropping the least recently used ones when the pool is full.
+    pub fn insert(&self, key: BackendKey, fork: Arc<WarmFork>) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut forks = self.forks.lock().unwrap();
+        self.prune(&mut forks);
+        while forks.len() >= self.size {
+            let Some(oldest) = forks
+                .iter()
+                .min_by_key(|(_, pooled)| pooled.last_used)
+                .map(|(key, _)| key.clone()) else {
+                break;
+            };
+            forks.remove(&oldest);
+        }
+        forks.insert(key, PooledFork { fork, last_used: Instant::now() });
+    }
+
+    pub fn evict(&self, key: &BackendKey) {
+        self.forks.lock().unwrap().remove(key);
+    }
+
+    fn prune(&self, forks: &mut HashMap<BackendKey, PooledFork>) {
+        forks.retain(|_, pooled| pooled.last_used.elapsed() < self.ttl);
+    }
+}
+
+impl WarmFork {
+    /// Forks `upstream` at `block_number`.
+    pub fn spawn(upstream: Arc<UpstreamLease>, block_number: u64) -> Result<Self, ForkError> {
+        let fork_opts = create_fork_opts(&upstream, Some(block_number))?;
+        let env = fork_opts.env.clone();
+        Ok(WarmFork {
+            upstream,
+            backend: Backend::spawn(Some(fork_opts)),
+            env,
+        })
+    }
+}
+
+impl fmt::Debug for WarmFork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WarmFork")
+            .field("upstream", &self.upstream)
+            .field("env", &self.env)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use foundry_evm::executor::Backend;
+
+    use crate::structs::{ BackendKey, BackendPool, UpstreamFailure, UpstreamLease, UpstreamPolicy, WarmFork };
+
+    fn key(block_number: u64) -> BackendKey {
+        BackendKey { chain_id: 1, block_number, upstream: "http://127.0.0.1:1".to_string() }
+    }
+
+    fn warm_fork() -> Arc<WarmFork> {
+        let policy = UpstreamPolicy {
+            retries: 0,
+            backoff: Duration::ZERO,
+            timeout: Duration::from_secs(1),
+            rate_limit: None,
+            budget: None,
+            cassette: None,
+        };
+        Arc::new(WarmFork {
+            upstream: UpstreamLease::new("http://127.0.0.1:1", 1, policy).unwrap(),
+            backend: Backend::spawn(None),
+            env: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_backend_pool() {
+        let pool = BackendPool::new(1, Duration::from_secs(60));
+        pool.insert(key(1), warm_fork());
+        assert!(pool.checkout(&key(1)).is_some());
+        assert!(pool.checkout(&key(2)).is_none());
+
+        // The least recently used fork makes room for the new one
+        pool.insert(key(2), warm_fork());
+        assert!(pool.checkout(&key(1)).is_none());
+        assert!(pool.checkout(&key(2)).is_some());
+
+        pool.evict(&key(2));
+        assert!(pool.checkout(&key(2)).is_none());
+
+        let pool = BackendPool::new(1, Duration::ZERO);
+        pool.insert(key(1), warm_fork());
+        assert!(pool.checkout(&key(1)).is_none());
+
+        let pool = BackendPool::new(0, Duration::from_secs(60));
+        pool.insert(key(1), warm_fork());
+        assert!(pool.checkout(&key(1)).is_none());
+    }
+
+    #[test]
+    fn test_backend_pool_leases() {
+        let pool = BackendPool::new(1, Duration::from_secs(60));
+        let fork = warm_fork();
+        pool.insert(key(1), Arc::clone(&fork));
+        let checkout = fork.upstream.checkout();
+
+        let route = &fork.upstream.route;
+        *route.failure.lock().unwrap() = Some(UpstreamFailure::Timeout);
+        route.failures.fetch_add(1, Ordering::Relaxed);
+
+        // Leases only see the failures since they were taken or reset
+        assert_eq!(checkout.failure(), Some(UpstreamFailure::Timeout));
+        assert_eq!(fork.upstream.checkout().failure(), None);
+        checkout.reset();
+        assert_eq!(checkout.failure(), None);
+
+        // Resetting a checkout leaves the failure the pool checks
+        assert_eq!(fork.upstream.failure(), Some(UpstreamFailure::Timeout));
+        assert!(pool.checkout(&key(1)).is_none());
+    }
+}
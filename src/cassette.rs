@@ -0,0 +1,102 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use serde_json::Value;
+
+use crate::structs::{ Cassette, CassetteFile, CassetteInteraction, CassetteMode, JsonRpcError };
+
+/// Held while a cassette is written, so that cassettes of the same file don't drop each other's
+/// recordings.
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+impl Cassette {
+    /// Opens the cassette at `path`. Replaying needs an existing cassette, recording adds to the
+    /// cassette if there is one.
+    pub fn load(path: &str, mode: CassetteMode) -> eyre::Result<Self> {
+        let file = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && mode == CassetteMode::Record => {
+                CassetteFile::default()
+            }
+            Err(err) => {
+                return Err(err.into());
+            }
+        };
+
+        Ok(Cassette {
+            path: PathBuf::from(path),
+            mode,
+            interactions: Mutex::new(file.interactions.into_iter().map(keyed).collect()),
+            dirty: Default::default(),
+        })
+    }
+
+    /// Recorded response to a request, as its result or error.
+    pub fn replay(
+        &self,
+        chain_id: u64,
+        method: &str,
+        params: &Value
+    ) -> Option<(Option<Value>, Option<JsonRpcError>)> {
+        let interactions = self.interactions.lock().unwrap();
+        let interaction = interactions.get(&key(chain_id, method, params))?;
+        Some((interaction.result.clone(), interaction.error.clone()))
+    }
+
+    pub fn record(
+        &self,
+        chain_id: u64,
+        method: &str,
+        params: &Value,
+        result: Option<Value>,
+        error: Option<JsonRpcError>
+    ) {
+        let interaction = CassetteInteraction {
+            chain_id,
+            method: method.to_string(),
+            params: params.clone(),
+            result,
+            error,
+        };
+        self.interactions.lock().unwrap().insert(key(chain_id, method, params), interaction);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Writes the cassette if requests were recorded since it was last written, keeping the
+    /// recordings other cassettes of the same file wrote in the meantime.
+    pub fn save(&self) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let _lock = SAVE_LOCK.lock().unwrap();
+        let mut interactions = self.interactions.lock().unwrap().clone();
+        if let Ok(bytes) = fs::read(&self.path) {
+            let file: CassetteFile = serde_json::from_slice(&bytes)?;
+            for (key, interaction) in file.interactions.into_iter().map(keyed) {
+                interactions.entry(key).or_insert(interaction);
+            }
+        }
+
+        let file = CassetteFile {
+            interactions: interactions.into_values().collect(),
+        };
+        // Write to a temporary file first so a crash never leaves a truncated cassette behind
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&file)?)?;
+        fs::rename(tmp_path, &self.path)
+    }
+}
+
+fn keyed(interaction: CassetteInteraction) -> (String, CassetteInteraction) {
+    (key(interaction.chain_id, &interaction.method, &interaction.params), interaction)
+}
+
+/// Requests are told apart by their chain, method and parameters. Request ids and upstream URLs
+/// are left out, so a cassette can be replayed with other upstreams.
+fn key(chain_id: u64, method: &str, params: &Value) -> String {
+    format!("{chain_id}:{method}:{params}")
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::time::{ Duration, Instant };
+
+use crate::structs::{
+    default_block_time,
+    ChainConfig,
+    ChainRegistry,
+    ChainResponse,
+    NativeCurrency,
+    NoURLForChainIdError,
+};
+
+/// How long an upstream that failed is tried after the healthy ones.
+const FAILED_UPSTREAM_COOLDOWN: Duration = Duration::from_secs(60);
+
+impl ChainRegistry {
+    /// Chains that are supported out of the box, with public RPC URLs.
+    pub fn builtin() -> Self {
+        let chains = [
+            chain(1, "Ethereum", "https://eth.llamarpc.com", ("Ether", "ETH"), 12),
+            chain(11155111, "Sepolia", "https://rpc.sepolia.org", ("Sepolia Ether", "ETH"), 12),
+            chain(137, "Polygon", "https://polygon-rpc.com", ("MATIC", "MATIC"), 2),
+            chain(43114, "Avalanche C-Chain", "https://api.avax.network/ext/bc/C/rpc", ("Avalanche", "AVAX"), 2),
+            chain(43113, "Avalanche Fuji", "https://api.avax-test.network/ext/bc/C/rpc", ("Avalanche", "AVAX"), 2),
+            chain(250, "Fantom", "https://rpcapi.fantom.network/", ("Fantom", "FTM"), 1),
+            chain(4002, "Fantom Testnet", "https://rpc.testnet.fantom.network/", ("Fantom", "FTM"), 1),
+            chain(100, "Gnosis", "https://rpc.gnosischain.com/", ("xDAI", "xDAI"), 5),
+            chain(56, "BNB Smart Chain", "https://bsc-dataseed.binance.org/", ("BNB", "BNB"), 3),
+            chain(97, "BNB Smart Chain Testnet", "https://data-seed-prebsc-1-s1.binance.org:8545/", ("BNB", "tBNB"), 3),
+            chain(42161, "Arbitrum One", "https://arb1.arbitrum.io/rpc", ("Ether", "ETH"), 1),
+            chain(10, "OP Mainnet", "https://mainnet.optimism.io/", ("Ether", "ETH"), 2),
+        ];
+
+        ChainRegistry {
+            chains: chains.into_iter().map(|chain| (chain.chain_id, chain)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds the chains of a JSON file holding an array of chains, replacing the ones with the
+    /// same chain id.
+    pub fn load_file(&mut self, path: &str) -> eyre::Result<()> {
+        let chains: Vec<ChainConfig> = serde_json::from_slice(&fs::read(path)?)?;
+        for chain in chains {
+            self.chains.insert(chain.chain_id, chain);
+        }
+        Ok(())
+    }
+
+    /// Replaces the RPC URLs of a chain, unknown chains are added with default settings.
+    pub fn set_rpc_urls(&mut self, chain_id: u64, rpc_urls: Vec<String>) {
+        self.chains
+            .entry(chain_id)
+            .or_insert_with(|| ChainConfig {
+                chain_id,
+                name: format!("Chain {chain_id}"),
+                rpc_urls: vec![],
+                native_currency: NativeCurrency::default(),
+                block_time: default_block_time(),
+            }).rpc_urls = rpc_urls;
+    }
+
+    pub fn get(&self, chain_id: u64) -> Option<&ChainConfig> {
+        self.chains.get(&chain_id)
+    }
+
+    /// RPC URLs of a chain in the order they should be tried. The first URL rotates between
+    /// calls to spread the load, URLs that failed recently are moved to the end.
+    pub fn upstreams(&self, chain_id: u64) -> Result<Vec<String>, NoURLForChainIdError> {
+        let rpc_urls = self.get(chain_id).map(|chain| &chain.rpc_urls).ok_or(NoURLForChainIdError)?;
+        if rpc_urls.is_empty() {
+            return Err(NoURLForChainIdError);
+        }
+
+        let start = self.health.next.fetch_add(1, Ordering::Relaxed) % rpc_urls.len();
+        let mut upstreams: Vec<String> = rpc_urls[start..]
+            .iter()
+            .chain(&rpc_urls[..start])
+            .cloned()
+            .collect();
+        upstreams.sort_by_key(|url| self.is_failing(url));
+        Ok(upstreams)
+    }
+
+    pub fn mark_failed(&self, url: &str) {
+        self.health.failures.insert(url.to_string(), Instant::now());
+    }
+
+    pub fn mark_healthy(&self, url: &str) {
+        self.health.failures.remove(url);
+    }
+
+    fn is_failing(&self, url: &str) -> bool {
+        self.health.failures
+            .get(url)
+            .map_or(false, |failed_at| failed_at.elapsed() < FAILED_UPSTREAM_COOLDOWN)
+    }
+
+    /// Seconds between blocks, used when a simulation moves to a later block.
+    pub fn block_time(&self, chain_id: u64) -> u64 {
+        self.get(chain_id).map_or(default_block_time(), |chain| chain.block_time)
+    }
+
+    /// The chains without their RPC URLs, which often contain API keys.
+    pub fn list(&self) -> Vec<ChainResponse> {
+        self.chains
+            .values()
+            .map(|chain| ChainResponse {
+                chain_id: chain.chain_id,
+                name: chain.name.clone(),
+                native_currency: chain.native_currency.clone(),
+                block_time: chain.block_time,
+            })
+            .collect()
+    }
+}
+
+fn chain(
+    chain_id: u64,
+    name: &str,
+    rpc_url: &str,
+    (currency_name, symbol): (&str, &str),
+    block_time: u64
+) -> ChainConfig {
+    ChainConfig {
+        chain_id,
+        name: name.to_string(),
+        rpc_urls: vec![rpc_url.to_string()],
+        native_currency: NativeCurrency {
+            name: currency_name.to_string(),
+            symbol: symbol.to_string(),
+            decimals: 18,
+        },
+        block_time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstreams() {
+        let mut chains = ChainRegistry::default();
+        chains.set_rpc_urls(1, vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+
+        assert_eq!(chains.upstreams(1).unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(chains.upstreams(1).unwrap(), vec!["b", "c", "a"]);
+
+        chains.mark_failed("c");
+        assert_eq!(chains.upstreams(1).unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(chains.upstreams(1).unwrap(), vec!["a", "b", "c"]);
+
+        chains.mark_healthy("c");
+        assert_eq!(chains.upstreams(1).unwrap(), vec!["b", "c", "a"]);
+
+        assert!(chains.upstreams(2).is_err());
+    }
+}
@@ -1,7 +1,9 @@
 use dotenvy::dotenv;
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::structs::Config;
+use crate::structs::{ BackendPool, Cassette, CassetteMode, ChainRegistry, Config, OfflineState, SimulationWorkers };
 
 macro_rules! get_env {
     ($name:expr) => {
@@ -24,30 +26,145 @@ pub fn config() -> Config {
 fn load_config() -> Config {
     Config {
         port: get_env!("PORT", 8080),
-        fork_url: get_env!("FORK_URL"),
+        chains: Arc::new(load_chains()),
+        offline_state: load_offline_state().map(Arc::new),
         etherscan_key: get_env!("ETHERSCAN_KEY"),
         api_key: get_env!("API_KEY"),
+        admin_api_key: get_env!("ADMIN_API_KEY"),
         max_request_size: get_env!("MAX_REQUEST_SIZE", 16) * 1024,
+        session_idle_ttl: get_env!("SESSION_IDLE_TTL").and_then(|ttl| ttl.parse().ok()),
+        session_max_ttl: get_env!("SESSION_MAX_TTL").and_then(|ttl| ttl.parse().ok()),
+        max_sessions: get_env!("MAX_SESSIONS").and_then(|max| max.parse().ok()),
+        max_sessions_per_api_key: get_env!("MAX_SESSIONS_PER_API_KEY").and_then(|max| max.parse().ok()),
+        session_memory_limit: get_env!("SESSION_MEMORY_LIMIT")
+            .and_then(|limit| limit.parse::<usize>().ok())
+            .map(|limit| limit * 1024 * 1024),
+        session_reaper_interval: get_env!("SESSION_REAPER_INTERVAL", 30),
+        session_store_dir: get_env!("SESSION_STORE_DIR"),
+        session_store_retention: get_env!("SESSION_STORE_RETENTION").and_then(|retention| retention.parse().ok()),
+        upstream_retries: get_env!("UPSTREAM_RETRIES", 3),
+        upstream_backoff: get_env!("UPSTREAM_BACKOFF", 500),
+        upstream_timeout: get_env!("UPSTREAM_TIMEOUT", 30),
+        upstream_rate_limit: get_env!("UPSTREAM_RATE_LIMIT").and_then(|limit| limit.parse().ok()),
+        simulation_rpc_budget: get_env!("SIMULATION_RPC_BUDGET").and_then(|budget| budget.parse().ok()),
+        rpc_cassette: load_rpc_cassette().map(Arc::new),
+        backend_pool: Arc::new(
+            BackendPool::new(get_env!("BACKEND_POOL_SIZE", 16), Duration::from_secs(get_env!("BACKEND_POOL_TTL", 300)))
+        ),
+        simulation_workers: Arc::new(
+            SimulationWorkers::new(
+                get_env!("SIMULATION_WORKERS", default_simulation_workers()),
+                get_env!("SIMULATION_QUEUE_SIZE").and_then(|size| size.parse().ok())
+            )
+        ),
     }
 }
 
+/// One simulation worker per CPU by default.
+fn default_simulation_workers() -> usize {
+    std::thread::available_parallelism().map_or(4, usize::from)
+}
+
+/// Cassette of `RPC_CASSETTE` that upstream requests are recorded to or replayed from, depending
+/// on `RPC_CASSETTE_MODE`, if set.
+fn load_rpc_cassette() -> Option<Cassette> {
+    let path = get_env!("RPC_CASSETTE")?;
+    let mode = match get_env!("RPC_CASSETTE_MODE").as_deref() {
+        None | Some("replay") => CassetteMode::Replay,
+        Some("record") => CassetteMode::Record,
+        Some(mode) => panic!("Invalid RPC_CASSETTE_MODE {mode}, expected record or replay"),
+    };
+    match Cassette::load(&path, mode) {
+        Ok(cassette) => Some(cassette),
+        Err(err) => panic!("Failed to load RPC cassette from {path}: {err}"),
+    }
+}
+
+/// State of `OFFLINE_STATE_FILE` that simulations start from instead of forking, if set.
+fn load_offline_state() -> Option<OfflineState> {
+    let path = get_env!("OFFLINE_STATE_FILE")?;
+    match OfflineState::load_file(&path) {
+        Ok(state) => Some(state),
+        Err(err) => panic!("Failed to load offline state from {path}: {err}"),
+    }
+}
+
+/// Built-in chains, replaced or extended by the chains of `CHAINS_FILE`. `RPC_URLS_{chainId}`
+/// holds comma-separated RPC URLs that take precedence over both.
+fn load_chains() -> ChainRegistry {
+    let mut chains = ChainRegistry::builtin();
+
+    if let Some(path) = get_env!("CHAINS_FILE") {
+        if let Err(err) = chains.load_file(&path) {
+            panic!("Failed to load chains from {path}: {err}");
+        }
+    }
+
+    for (name, value) in env::vars() {
+        let Some(chain_id) = name.strip_prefix("RPC_URLS_").and_then(|id| id.parse().ok()) else {
+            continue;
+        };
+        let rpc_urls: Vec<String> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(String::from)
+            .collect();
+        if !rpc_urls.is_empty() {
+            chains.set_rpc_urls(chain_id, rpc_urls);
+        }
+    }
+
+    chains
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
-    fn test_config_fork_url() {
-        temp_env::with_vars([("FORK_URL", Some("a"))], || {
+    fn test_config_chains() {
+        temp_env::with_vars([("RPC_URLS_1", Some("a, b")), ("RPC_URLS_999", Some("c"))], || {
+            let config = super::load_config();
+            let chain = config.chains.get(1).unwrap();
+            assert_eq!(chain.rpc_urls, vec!["a".to_string(), "b".to_string()]);
+            assert_eq!(chain.name, "Ethereum");
+            assert_eq!(config.chains.upstreams(999).ok(), Some(vec!["c".to_string()]));
+            assert_eq!(config.chains.block_time(999), 12);
+        });
+
+        let path = std::env::temp_dir().join("symunix-test-chains.json");
+        std::fs::write(
+            &path,
+            r#"[{ "chainId": 1, "name": "Mainnet", "rpcUrls": ["d"], "blockTime": 13 }]"#,
+        ).unwrap();
+        temp_env::with_vars([("CHAINS_FILE", path.to_str())], || {
             let config = super::load_config();
-            assert_eq!(config.fork_url, Some("a".to_string()));
+            assert_eq!(config.chains.upstreams(1).ok(), Some(vec!["d".to_string()]));
+            assert_eq!(config.chains.block_time(1), 13);
+            assert_eq!(config.chains.get(1).unwrap().native_currency.symbol, "ETH");
+            assert_eq!(config.chains.block_time(137), 2);
         });
 
-        temp_env::with_vars([("FORK_URL", Some(""))], || {
+        temp_env::with_vars_unset(["CHAINS_FILE"], || {
             let config = super::load_config();
-            assert_eq!(config.fork_url, None);
+            assert!(config.chains.get(5).is_none());
+            assert!(config.chains.get(80001).is_none());
         });
+    }
 
-        temp_env::with_vars_unset(["FORK_URL"], || {
+    #[test]
+    fn test_config_offline_state() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/genesis.json");
+        temp_env::with_vars([("OFFLINE_STATE_FILE", Some(path))], || {
             let config = super::load_config();
-            assert_eq!(config.fork_url, None);
+            let offline_state = config.offline_state.unwrap();
+            assert_eq!(offline_state.chain_id, 1337);
+            assert_eq!(offline_state.block_number(), 1);
+            assert_eq!(offline_state.state.accounts.len(), 2);
+        });
+
+        temp_env::with_vars_unset(["OFFLINE_STATE_FILE"], || {
+            let config = super::load_config();
+            assert!(config.offline_state.is_none());
         });
     }
 
@@ -69,6 +186,129 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_config_session_limits() {
+        temp_env::with_vars(
+            [
+                ("SESSION_IDLE_TTL", Some("600")),
+                ("MAX_SESSIONS", Some("10")),
+                ("SESSION_MEMORY_LIMIT", Some("512")),
+            ],
+            || {
+                let config = super::load_config();
+                assert_eq!(config.session_idle_ttl, Some(600));
+                assert_eq!(config.max_sessions, Some(10));
+                assert_eq!(config.session_memory_limit, Some(512 * 1024 * 1024));
+            },
+        );
+
+        temp_env::with_vars([("SESSION_IDLE_TTL", Some("")), ("MAX_SESSIONS", Some("a"))], || {
+            let config = super::load_config();
+            assert_eq!(config.session_idle_ttl, None);
+            assert_eq!(config.max_sessions, None);
+        });
+    }
+
+    #[test]
+    fn test_config_session_store() {
+        temp_env::with_vars(
+            [("SESSION_STORE_DIR", Some("/tmp/sessions")), ("SESSION_STORE_RETENTION", Some("3600"))],
+            || {
+                let config = super::load_config();
+                assert_eq!(config.session_store_dir, Some("/tmp/sessions".to_string()));
+                assert_eq!(config.session_store_retention, Some(3600));
+            },
+        );
+
+        temp_env::with_vars_unset(["SESSION_STORE_DIR", "SESSION_STORE_RETENTION"], || {
+            let config = super::load_config();
+            assert_eq!(config.session_store_dir, None);
+            assert_eq!(config.session_store_retention, None);
+        });
+    }
+
+    #[test]
+    fn test_config_upstream() {
+        temp_env::with_vars(
+            [
+                ("UPSTREAM_RETRIES", Some("5")),
+                ("UPSTREAM_RATE_LIMIT", Some("20")),
+                ("SIMULATION_RPC_BUDGET", Some("100")),
+            ],
+            || {
+                let config = super::load_config();
+                assert_eq!(config.upstream_retries, 5);
+                assert_eq!(config.upstream_rate_limit, Some(20));
+                assert_eq!(config.simulation_rpc_budget, Some(100));
+            },
+        );
+
+        temp_env::with_vars_unset(
+            ["UPSTREAM_RETRIES", "UPSTREAM_BACKOFF", "UPSTREAM_TIMEOUT", "UPSTREAM_RATE_LIMIT", "SIMULATION_RPC_BUDGET"],
+            || {
+                let config = super::load_config();
+                assert_eq!(config.upstream_retries, 3);
+                assert_eq!(config.upstream_backoff, 500);
+                assert_eq!(config.upstream_timeout, 30);
+                assert_eq!(config.upstream_rate_limit, None);
+                assert_eq!(config.simulation_rpc_budget, None);
+            },
+        );
+    }
+
+    #[test]
+    fn test_config_rpc_cassette() {
+        let path = std::env::temp_dir().join("symunix-test-config-cassette.json");
+        let _ = std::fs::remove_file(&path);
+        temp_env::with_vars([("RPC_CASSETTE", path.to_str()), ("RPC_CASSETTE_MODE", Some("record"))], || {
+            let config = super::load_config();
+            let cassette = config.rpc_cassette.unwrap();
+            assert_eq!(cassette.mode, crate::structs::CassetteMode::Record);
+            assert!(cassette.interactions.lock().unwrap().is_empty());
+        });
+
+        std::fs::write(&path, r#"{ "interactions": [] }"#).unwrap();
+        temp_env::with_vars([("RPC_CASSETTE", path.to_str())], || {
+            let config = super::load_config();
+            assert_eq!(config.rpc_cassette.unwrap().mode, crate::structs::CassetteMode::Replay);
+        });
+
+        temp_env::with_vars_unset(["RPC_CASSETTE"], || {
+            let config = super::load_config();
+            assert!(config.rpc_cassette.is_none());
+        });
+    }
+
+    #[test]
+    fn test_config_backend_pool() {
+        temp_env::with_vars([("BACKEND_POOL_SIZE", Some("0")), ("BACKEND_POOL_TTL", Some("60"))], || {
+            let config = super::load_config();
+            assert!(!config.backend_pool.is_enabled());
+            assert_eq!(config.backend_pool.ttl, std::time::Duration::from_secs(60));
+        });
+
+        temp_env::with_vars_unset(["BACKEND_POOL_SIZE", "BACKEND_POOL_TTL"], || {
+            let config = super::load_config();
+            assert_eq!(config.backend_pool.size, 16);
+            assert_eq!(config.backend_pool.ttl, std::time::Duration::from_secs(300));
+        });
+    }
+
+    #[test]
+    fn test_config_simulation_workers() {
+        temp_env::with_vars([("SIMULATION_WORKERS", Some("2")), ("SIMULATION_QUEUE_SIZE", Some("10"))], || {
+            let config = super::load_config();
+            assert_eq!(config.simulation_workers.workers, 2);
+            assert_eq!(config.simulation_workers.queue_size, Some(10));
+        });
+
+        temp_env::with_vars_unset(["SIMULATION_WORKERS", "SIMULATION_QUEUE_SIZE"], || {
+            let config = super::load_config();
+            assert_eq!(config.simulation_workers.workers, super::default_simulation_workers());
+            assert_eq!(config.simulation_workers.queue_size, None);
+        });
+    }
+
     #[test]
     fn test_config_api_key() {
         temp_env::with_vars([("API_KEY", Some("a"))], || {
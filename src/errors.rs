@@ -14,10 +14,26 @@ use crate::structs::{
     MultipleBlockNumbersError,
     InvalidBlockNumbersError,
     StateNotFound,
+    SnapshotNotFound,
+    SessionExpired,
+    SessionLimitReached,
+    UnauthorizedError,
     OverrideError,
     EvmError,
     FailedInstantiateFork,
+    ForkError,
+    BlockNotFound,
+    HistoricalStateUnavailable,
+    UpstreamUnreachable,
+    UpstreamChainMismatch,
+    UpstreamNotRecorded,
+    SimulationQueueFull,
     FailedToSetBlockTimestamp,
+    InvalidRawTransactionError,
+    ContractCreationNotSupported,
+    UpstreamRateLimited,
+    UpstreamTimeout,
+    UpstreamBudgetExceeded,
 };
 
 impl Reject for NoURLForChainIdError {}
@@ -32,29 +48,85 @@ impl Reject for InvalidBlockNumbersError {}
 
 impl Reject for StateNotFound {}
 
+impl Reject for SnapshotNotFound {}
+
+impl Reject for SessionExpired {}
+
+impl Reject for SessionLimitReached {}
+
+impl Reject for UnauthorizedError {}
+
 impl Reject for OverrideError {}
 
 impl Reject for EvmError {}
 
 impl Reject for FailedInstantiateFork {}
 
+impl Reject for BlockNotFound {}
+
+impl Reject for HistoricalStateUnavailable {}
+
+impl Reject for UpstreamUnreachable {}
+
+impl Reject for UpstreamChainMismatch {}
+
+impl Reject for UpstreamNotRecorded {}
+
+impl Reject for SimulationQueueFull {}
+
 impl Reject for FailedToSetBlockTimestamp {}
 
+impl Reject for InvalidRawTransactionError {}
+
+impl Reject for ContractCreationNotSupported {}
+
+impl Reject for UpstreamRateLimited {}
+
+impl Reject for UpstreamTimeout {}
+
+impl Reject for UpstreamBudgetExceeded {}
+
+impl From<ForkError> for Rejection {
+    fn from(err: ForkError) -> Self {
+        match err {
+            ForkError::BlockNotFound => warp::reject::custom(BlockNotFound),
+            ForkError::HistoricalStateUnavailable => warp::reject::custom(HistoricalStateUnavailable),
+            ForkError::ChainMismatch => warp::reject::custom(UpstreamChainMismatch),
+            ForkError::Failed => warp::reject::custom(FailedInstantiateFork),
+        }
+    }
+}
+
 pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     let (code, message) = match err {
         e if e.is_not_found() => (StatusCode::NOT_FOUND, "NOT_FOUND".to_string()),
         e if e.find::<StateNotFound>().is_some() => (StatusCode::NOT_FOUND, "STATE_NOT_FOUND".to_string()),
+        e if e.find::<SessionExpired>().is_some() => (StatusCode::GONE, "SESSION_EXPIRED".to_string()),
+        e if e.find::<SessionLimitReached>().is_some() => (StatusCode::TOO_MANY_REQUESTS, "SESSION_LIMIT_REACHED".to_string()),
+        e if e.find::<SnapshotNotFound>().is_some() => (StatusCode::NOT_FOUND, "SNAPSHOT_NOT_FOUND".to_string()),
         e if e.find::<NoURLForChainIdError>().is_some() => (StatusCode::BAD_REQUEST, "CHAIN_ID_NOT_SUPPORTED".to_string()),
         e if e.find::<IncorrectChainIdError>().is_some() => (StatusCode::BAD_REQUEST, "INCORRECT_CHAIN_ID".to_string()),
         e if e.find::<MultipleChainIdsError>().is_some() => (StatusCode::BAD_REQUEST, "MULTIPLE_CHAIN_IDS".to_string()),
         e if e.find::<MultipleBlockNumbersError>().is_some() => (StatusCode::BAD_REQUEST, "MULTIPLE_BLOCK_NUMBERS".to_string()),
         e if e.find::<InvalidBlockNumbersError>().is_some() => (StatusCode::BAD_REQUEST, "INVALID_BLOCK_NUMBERS".to_string()),
+        e if e.find::<InvalidRawTransactionError>().is_some() => (StatusCode::BAD_REQUEST, "INVALID_RAW_TRANSACTION".to_string()),
+        e if e.find::<ContractCreationNotSupported>().is_some() => (StatusCode::BAD_REQUEST, "CONTRACT_CREATION_NOT_SUPPORTED".to_string()),
         e if e.find::<BodyDeserializeError>().is_some() => {
             let cause = e.find::<BodyDeserializeError>().unwrap().source().map(|cause| format!("{}", cause)).unwrap_or_default();
             (StatusCode::BAD_REQUEST, format!("BAD REQUEST: {}", cause))
         }
         e if e.find::<warp::reject::MethodNotAllowed>().is_some() => (StatusCode::METHOD_NOT_ALLOWED, "METHOD_NOT_ALLOWED".to_string()),
         e if e.find::<warp::reject::MissingHeader>().is_some() => (StatusCode::UNAUTHORIZED, "UNAUTHORIZED".to_string()),
+        e if e.find::<UnauthorizedError>().is_some() => (StatusCode::UNAUTHORIZED, "UNAUTHORIZED".to_string()),
+        e if e.find::<SimulationQueueFull>().is_some() => (StatusCode::SERVICE_UNAVAILABLE, "SIMULATION_QUEUE_FULL".to_string()),
+        e if e.find::<UpstreamRateLimited>().is_some() => (StatusCode::SERVICE_UNAVAILABLE, "UPSTREAM_RATE_LIMITED".to_string()),
+        e if e.find::<UpstreamTimeout>().is_some() => (StatusCode::GATEWAY_TIMEOUT, "UPSTREAM_TIMEOUT".to_string()),
+        e if e.find::<UpstreamBudgetExceeded>().is_some() => (StatusCode::BAD_REQUEST, "UPSTREAM_BUDGET_EXCEEDED".to_string()),
+        e if e.find::<UpstreamUnreachable>().is_some() => (StatusCode::BAD_GATEWAY, "UPSTREAM_UNREACHABLE".to_string()),
+        e if e.find::<UpstreamChainMismatch>().is_some() => (StatusCode::BAD_GATEWAY, "UPSTREAM_CHAIN_MISMATCH".to_string()),
+        e if e.find::<UpstreamNotRecorded>().is_some() => (StatusCode::BAD_GATEWAY, "UPSTREAM_NOT_RECORDED".to_string()),
+        e if e.find::<BlockNotFound>().is_some() => (StatusCode::NOT_FOUND, "BLOCK_NOT_FOUND".to_string()),
+        e if e.find::<HistoricalStateUnavailable>().is_some() => (StatusCode::UNPROCESSABLE_ENTITY, "HISTORICAL_STATE_UNAVAILABLE".to_string()),
         e if e.find::<FailedInstantiateFork>().is_some() => (StatusCode::INTERNAL_SERVER_ERROR, "FAILED_INSTANTIATE_FORK".to_string()),
         e if e.find::<warp::reject::InvalidHeader>().is_some() => (StatusCode::BAD_REQUEST, "INVALID_HEADER".to_string()),
         e if e.find::<FailedToSetBlockTimestamp>().is_some() => (StatusCode::INTERNAL_SERVER_ERROR, "FAILED_TO_SET_BLOCK_TIMESTAMP".to_string()),
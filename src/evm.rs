@@ -1,29 +1,60 @@
-
-
+use std::collections::{ BTreeMap, HashMap };
+use std::sync::Arc;
 use ethers::abi::{ Address, Uint };
+use ethers::abi::ethereum_types::BloomInput;
 
 use ethers::types::transaction::eip2930::AccessList;
-use ethers::types::Bytes;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::providers::{ Http, Middleware, Provider };
+use ethers::types::{
+    Block,
+    BlockNumber,
+    Bloom,
+    Bytes,
+    Filter,
+    FilteredParams,
+    Log,
+    Transaction,
+    TransactionReceipt,
+    TransactionRequest,
+    H256,
+    U64,
+};
+use ethers::utils::keccak256;
+use eyre::eyre;
 use foundry_config::Chain;
+use foundry_evm::executor::backend::DatabaseExt;
 use foundry_evm::executor::fork::CreateFork;
 use foundry_evm::executor::{ opts::EvmOpts, Backend, ExecutorBuilder };
 use foundry_evm::trace::identifier::{ EtherscanIdentifier, SignaturesIdentifier };
 use foundry_evm::trace::node::CallTraceNode;
-use foundry_evm::trace::CallTraceDecoderBuilder;
-use foundry_evm::utils::{ h160_to_b160, u256_to_ru256 };
-use revm::db::DatabaseRef;
+use foundry_evm::trace::{ CallTraceDecoder, CallTraceDecoderBuilder };
+use foundry_evm::utils::{ b160_to_h160, h160_to_b160, ru256_to_u256, u256_to_ru256 };
+use revm::db::{ AccountState, CacheDB, DatabaseRef };
 use revm::primitives::{ Account, Bytecode, Env, StorageSlot };
-use revm::DatabaseCommit;
+use revm::{ DatabaseCommit, JournaledState };
 use crate::structs::CallTrace;
 
 use crate::structs::{
+    InactiveFork,
+    EvmCheckpoint,
+    SessionTransaction,
+    SerializableAccountRecord,
+    SerializableState,
     CallRawRequest,
     CallRawResult,
     StorageOverride,
+    WrittenSlots,
+    PersistedFork,
+    AccountChanges,
+    LogsQuery,
     Evm,
     EvmError, 
     OverrideError, 
-    FailedInstantiateFork,
+    ForkError,
+    ForkSource,
+    UpstreamFailure,
+    UpstreamLease,
 };
 
 impl From<CallTraceNode> for CallTrace {
@@ -39,46 +70,38 @@ impl From<CallTraceNode> for CallTrace {
 
 
 impl Evm {
+    /// Forks an upstream through the upstream proxy, starts from a warm fork of the backend pool
+    /// or, offline, starts from the offline state without any upstream.
     pub fn new(
         env: Option<Env>,
-        fork_url: String,
+        source: ForkSource,
         fork_block_number: Option<u64>,
         gas_limit: u64,
         tracing: bool,
         etherscan_key: Option<String>
-    ) -> Result<Self, FailedInstantiateFork> {
-        let evm_opts = EvmOpts {
-            fork_url: Some(fork_url.clone()),
-            fork_block_number,
-            env: foundry_evm::executor::opts::Env {
-                chain_id: None,
-                code_size_limit: None,
-                gas_price: Some(0),
-                gas_limit: u64::MAX,
-                ..Default::default()
-            },
-            memory_limit: foundry_config::Config::default().memory_limit,
-            ..Default::default()
-        };
-
-        let env_result = evm_opts.evm_env_blocking();
-        let envi = match env_result {
-            Ok(envi) => envi,
-            Err(err) => {
-                eprintln!("Failed to instantiate forked environment: {}", err);
-                return Err(FailedInstantiateFork);
+    ) -> Result<Self, ForkError> {
+        let (fork_env, db, fork_url, upstreams) = match &source {
+            ForkSource::Upstream(upstream) => {
+                let fork_opts = create_fork_opts(upstream, fork_block_number)?;
+                let fork_env = fork_opts.env.clone();
+                let fork_url = upstream.upstream_url().to_string();
+                (fork_env, Backend::spawn(Some(fork_opts)), fork_url, vec![Arc::clone(upstream)])
+            }
+            // Clones of the warm backend share its cache of upstream data, changes stay local
+            ForkSource::Pooled(fork, upstream) => {
+                let fork_url = upstream.upstream_url().to_string();
+                (fork.env.clone(), fork.backend.clone(), fork_url, vec![Arc::clone(upstream)])
+            }
+            ForkSource::Offline(state) => {
+                // The offline state is all there is, there are no other blocks to start from
+                if fork_block_number.map_or(false, |number| number != state.block_number()) {
+                    return Err(ForkError::BlockNotFound);
+                }
+                let env = evm_opts(None, None, Some(state.chain_id)).local_evm_env();
+                (env, Backend::spawn(None), OFFLINE_FORK_URL.to_string(), Vec::new())
             }
         };
 
-        let fork_opts = CreateFork {
-            url: fork_url.clone(),
-            enable_caching: true,
-            env: envi,
-            evm_opts: evm_opts.clone(),
-        };
-
-        let db = Backend::spawn(Some(fork_opts.clone()));
-
         let builder = ExecutorBuilder::default()
             .with_gas_limit(gas_limit.into())
             .set_tracing(tracing);
@@ -86,32 +109,156 @@ impl Evm {
         let executor = if let Some(env) = env {
             builder.with_config(env).build(db)
         } else {
-            builder.with_config(fork_opts.env.clone()).build(db)
+            builder.with_config(fork_env.clone()).build(db)
         };
 
-        let foundry_config = foundry_config::Config {
-            etherscan_api_key: etherscan_key,
-            ..Default::default()
-        };
+        let chain: Chain = fork_env.cfg.chain_id.to::<u64>().into();
+        let (decoder, etherscan_identifier) = build_decoder(chain, etherscan_key);
 
-        let chain: Chain = fork_opts.env.cfg.chain_id.to::<u64>().into();
-        let etherscan_identifier = EtherscanIdentifier::new(&foundry_config, Some(chain)).ok();
-        let mut decoder = CallTraceDecoderBuilder::new().with_verbosity(5).build();
+        let mut evm = Evm {
+            executor,
+            decoder,
+            etherscan_identifier,
+            snapshots: HashMap::new(),
+            next_snapshot_id: Uint::zero(),
+            fork_url,
+            fork_block_number: fork_env.block.number.to(),
+            gas_limit,
+            transaction_count: 0,
+            transactions: Vec::new(),
+            record_transactions: false,
+            blocks: Vec::new(),
+            written_slots: HashMap::new(),
+            time_offset: 0,
+            forks: HashMap::new(),
+            upstreams,
+        };
 
-        if
-            let Ok(identifier) = SignaturesIdentifier::new(
-                foundry_config::Config::foundry_cache_dir(),
-                false
-            )
-        {
-            decoder.add_signature_identifier(identifier);
+        if let ForkSource::Offline(state) = source {
+            evm.load_state(state.state.clone()).map_err(|_| ForkError::Failed)?;
+            evm.fork_block_number = state.block_number();
+            // The offline state is what the EVM starts from, like the fork block of a fork
+            evm.written_slots.clear();
         }
 
-        Ok(Evm {
-            executor,
+        Ok(evm)
+    }
+
+    /// Whether this EVM runs on the offline state, without any upstream.
+    pub fn is_offline(&self) -> bool {
+        self.upstreams.is_empty()
+    }
+
+    /// Creates an independent copy of this EVM with the same backend state, env and snapshots.
+    /// The trace decoder can't be cloned, a new one is built instead.
+    pub fn duplicate(&self, etherscan_key: Option<String>) -> Self {
+        let chain: Chain = self.executor.env().cfg.chain_id.to::<u64>().into();
+        let (decoder, etherscan_identifier) = build_decoder(chain, etherscan_key);
+
+        Evm {
+            executor: self.executor.clone(),
             decoder,
             etherscan_identifier,
-        })
+            snapshots: self.snapshots.clone(),
+            next_snapshot_id: self.next_snapshot_id,
+            fork_url: self.fork_url.clone(),
+            fork_block_number: self.fork_block_number,
+            gas_limit: self.gas_limit,
+            transaction_count: self.transaction_count,
+            transactions: self.transactions.clone(),
+            record_transactions: self.record_transactions,
+            blocks: self.blocks.clone(),
+            written_slots: self.written_slots.clone(),
+            time_offset: self.time_offset,
+            forks: self.forks.clone(),
+            upstreams: self.upstreams.clone(),
+        }
+    }
+
+    /// Adds a fork of another chain, transactions are routed to it with `select_chain`. Fails
+    /// if the upstream is not on `chain_id`.
+    pub fn add_fork(
+        &mut self,
+        upstream: Arc<UpstreamLease>,
+        chain_id: u64,
+        fork_block_number: Option<u64>
+    ) -> Result<(), ForkError> {
+        let fork_opts = create_fork_opts(&upstream, fork_block_number)?;
+        if fork_opts.env.cfg.chain_id.to::<u64>() != chain_id {
+            return Err(ForkError::ChainMismatch);
+        }
+        if self.has_chain(chain_id) {
+            return Ok(());
+        }
+
+        let block = fork_opts.env.block.clone();
+        let fork_id = self.executor
+            .backend_mut()
+            .create_fork(fork_opts)
+            .map_err(|err| {
+                log::warn!(target: "ts::api", "Failed to create fork: {err:#}");
+                ForkError::Failed
+            })?;
+
+        self.forks.insert(chain_id, InactiveFork {
+            fork_id: fork_id.into(),
+            fork_url: upstream.upstream_url().to_string(),
+            fork_block_number: block.number.to(),
+            block,
+            transactions: Vec::new(),
+            blocks: Vec::new(),
+            written_slots: HashMap::new(),
+        });
+        self.upstreams.push(upstream);
+        Ok(())
+    }
+
+    pub fn has_chain(&self, chain_id: u64) -> bool {
+        self.get_chain_id().as_u64() == chain_id || self.forks.contains_key(&chain_id)
+    }
+
+    /// Makes the fork of `chain_id` the active one. Each fork keeps its own state, block env and
+    /// history.
+    pub fn select_chain(&mut self, chain_id: u64) -> Result<(), EvmError> {
+        let active_chain_id = self.get_chain_id().as_u64();
+        if active_chain_id == chain_id {
+            return Ok(());
+        }
+        let Some(active_fork_id) = self.executor.backend().active_fork_id() else {
+            return Err(EvmError(eyre!("no active fork")));
+        };
+        let Some(target) = self.forks.remove(&chain_id) else {
+            return Err(EvmError(eyre!("no fork for chain id {chain_id}")));
+        };
+
+        // Selecting a fork overwrites the env with the one the fork was created with
+        let mut env = self.executor.env().clone();
+        let active_block = env.block.clone();
+        if let Err(err) = self.executor
+            .backend_mut()
+            .select_fork(target.fork_id.into(), &mut env, &mut JournaledState::new(0))
+        {
+            self.forks.insert(chain_id, target);
+            return Err(EvmError(err));
+        }
+
+        let active = InactiveFork {
+            fork_id: active_fork_id.into(),
+            fork_url: std::mem::replace(&mut self.fork_url, target.fork_url),
+            fork_block_number: std::mem::replace(
+                &mut self.fork_block_number,
+                target.fork_block_number
+            ),
+            block: active_block,
+            transactions: std::mem::replace(&mut self.transactions, target.transactions),
+            blocks: std::mem::replace(&mut self.blocks, target.blocks),
+            written_slots: std::mem::replace(&mut self.written_slots, target.written_slots),
+        };
+        env.block = target.block;
+        *self.executor.env_mut() = env;
+        self.forks.insert(active_chain_id, active);
+
+        Ok(())
     }
 
     pub async fn call_raw(
@@ -154,6 +301,7 @@ impl Evm {
             exit_reason: res.exit_reason,
             return_data: Bytes(res.result),
             formatted_trace,
+            transaction_hash: None,
         })
     }
 
@@ -186,11 +334,26 @@ impl Evm {
         if let Some(storage) = storage {
             self.handle_storage_override(&mut account, storage)?;
         }
+        for (slot, value) in &account.storage {
+            let original = self.executor.backend().storage(address, *slot).map_err(|_| OverrideError)?;
+            if original != value.present_value {
+                self.record_written_slot(
+                    b160_to_h160(address),
+                    ru256_to_u256(*slot),
+                    ru256_to_u256(original)
+                );
+            }
+        }
         self.executor.backend_mut().commit([(address, account)].into_iter().collect());
 
         Ok(())
     }
 
+    /// Remembers that a slot was written, along with the value it had before it was first written.
+    fn record_written_slot(&mut self, address: Address, slot: Uint, original: Uint) {
+        self.written_slots.entry(address).or_default().entry(slot).or_insert(original);
+    }
+
     fn handle_storage_override(
         &self,
         account: &mut Account,
@@ -210,13 +373,23 @@ impl Evm {
         Ok(())
     }
 
+    /// Commits a transaction and records it in the history of this EVM if it keeps one.
+    /// Transactions are not signed, their hash is derived from the unsigned transaction, its
+    /// sender and its position in the history.
     pub async fn call_raw_committing(
         &mut self,
         call: CallRawRequest,
         gas_limit: u64
     ) -> Result<CallRawResult, EvmError> {
+        let transaction = if self.record_transactions {
+            Some(self.build_transaction(&call, gas_limit)?)
+        } else {
+            None
+        };
+
         self.executor.set_gas_limit(gas_limit.into());
         self.set_access_list(call.access_list);
+        let block = self.executor.env().block.clone();
         let res = self.executor
             .call_raw_committing(
                 call.from,
@@ -228,6 +401,16 @@ impl Evm {
                 dbg!(&err);
                 EvmError(err)
             })?;
+        self.transaction_count += 1;
+        for (address, account) in res.state_changeset.iter().flatten() {
+            for (slot, value) in account.storage.iter().filter(|(_, value)| value.is_changed()) {
+                self.record_written_slot(
+                    b160_to_h160(*address),
+                    ru256_to_u256(*slot),
+                    ru256_to_u256(value.original_value)
+                );
+            }
+        }
 
         let formatted_trace = if call.format_trace {
             let mut output = String::new();
@@ -243,18 +426,343 @@ impl Evm {
             None
         };
 
+        let Some(mut transaction) = transaction else {
+            return Ok(CallRawResult {
+                gas_used: res.gas_used,
+                block_number: block.number.to(),
+                success: !res.reverted,
+                trace: res.traces,
+                logs: res.logs,
+                exit_reason: res.exit_reason,
+                return_data: Bytes(res.result),
+                formatted_trace,
+                transaction_hash: None,
+            });
+        };
+
+        let block_number = U64::from(block.number.to::<u64>());
+        let (transaction_index, cumulative_gas_used, log_index) = self.transactions
+            .iter()
+            .filter(|transaction| transaction.receipt.block_number == Some(block_number))
+            .fold((0u64, Uint::zero(), 0usize), |(index, gas, logs), transaction| {
+                let receipt = &transaction.receipt;
+                (index + 1, gas + receipt.gas_used.unwrap_or_default(), logs + receipt.logs.len())
+            });
+        transaction.block_number = Some(block_number);
+        transaction.transaction_index = Some(transaction_index.into());
+
+        let mut logs_bloom = Bloom::default();
+        let logs: Vec<Log> = res.logs
+            .into_iter()
+            .enumerate()
+            .map(|(i, log)| {
+                logs_bloom.accrue(BloomInput::Raw(log.address.as_bytes()));
+                for topic in &log.topics {
+                    logs_bloom.accrue(BloomInput::Raw(topic.as_bytes()));
+                }
+                Log {
+                    block_number: Some(block_number),
+                    transaction_hash: Some(transaction.hash),
+                    transaction_index: Some(transaction_index.into()),
+                    log_index: Some((log_index + i).into()),
+                    transaction_log_index: Some(i.into()),
+                    removed: Some(false),
+                    ..log
+                }
+            })
+            .collect();
+
+        let receipt = TransactionReceipt {
+            transaction_hash: transaction.hash,
+            transaction_index: transaction_index.into(),
+            block_number: Some(block_number),
+            from: transaction.from,
+            to: transaction.to,
+            cumulative_gas_used: cumulative_gas_used + res.gas_used,
+            gas_used: Some(res.gas_used.into()),
+            logs: logs.clone(),
+            status: Some((!res.reverted as u64).into()),
+            logs_bloom,
+            effective_gas_price: transaction.gas_price,
+            ..Default::default()
+        };
+        let hash = transaction.hash;
+        let return_data = Bytes(res.result);
+        self.transactions.push(Arc::new(SessionTransaction {
+            transaction,
+            receipt,
+            block,
+            return_data: return_data.clone(),
+            exit_reason: res.exit_reason,
+        }));
+
         Ok(CallRawResult {
             gas_used: res.gas_used,
-            block_number: res.env.block.number.to(),
+            block_number: block_number.as_u64(),
             success: !res.reverted,
-            trace: res.traces.clone(), // Clonazione potrebbe essere evitata se non è necessaria
-            logs: res.logs,
+            trace: res.traces,
+            logs,
             exit_reason: res.exit_reason,
-            return_data: Bytes(res.result),
+            return_data,
             formatted_trace,
+            transaction_hash: Some(hash),
         })
     }
 
+    fn build_transaction(&self, call: &CallRawRequest, gas_limit: u64) -> Result<Transaction, EvmError> {
+        let nonce = self.get_nonce(call.from)?;
+        let chain_id = self.get_chain_id().as_u64();
+        let request: TypedTransaction = TransactionRequest::new()
+            .from(call.from)
+            .to(call.to)
+            .nonce(nonce)
+            .gas(gas_limit)
+            .value(call.value.unwrap_or_default())
+            .data(call.data.clone().unwrap_or_default())
+            .chain_id(chain_id)
+            .into();
+        let hash = H256::from(
+            keccak256(
+                [
+                    request.sighash().as_bytes(),
+                    call.from.as_bytes(),
+                    &(self.transactions.len() as u64).to_be_bytes(),
+                ].concat()
+            )
+        );
+
+        Ok(Transaction {
+            hash,
+            nonce: nonce.into(),
+            from: call.from,
+            to: Some(call.to),
+            value: call.value.unwrap_or_default(),
+            gas_price: Some(self.executor.env().tx.gas_price.into()),
+            gas: gas_limit.into(),
+            input: call.data.clone().unwrap_or_default(),
+            chain_id: Some(chain_id.into()),
+            ..Default::default()
+        })
+    }
+
+    pub fn get_transaction(&self, hash: H256) -> Option<&SessionTransaction> {
+        self.transactions
+            .iter()
+            .find(|transaction| transaction.transaction.hash == hash)
+            .map(Arc::as_ref)
+    }
+
+    /// Seals the transactions of the current block into a block with a header and moves on to
+    /// the next block. Transactions left in earlier blocks, when blocks were skipped without
+    /// mining, are sealed into their own blocks first.
+    pub fn mine(&mut self, block_time: u64) -> Block<H256> {
+        let current = U64::from(self.get_block().as_u64());
+        let mut numbers: Vec<U64> = self.transactions
+            .iter()
+            .filter(|transaction| transaction.receipt.block_hash.is_none())
+            .filter_map(|transaction| transaction.receipt.block_number)
+            .filter(|number| *number < current)
+            .collect();
+        numbers.sort();
+        numbers.dedup();
+
+        for number in numbers {
+            self.seal_block(number);
+        }
+        let block = self.seal_block(current);
+
+        let gas_used = block.gas_used.low_u64();
+        self.advance_blocks(1, block_time, gas_used);
+
+        block
+    }
+
+    fn seal_block(&mut self, number: U64) -> Block<H256> {
+        let parent_hash = self.get_block_hash(number.saturating_sub(1.into()));
+        let env_block = self.executor.env().block.clone();
+        // Sealing copies the transactions that snapshots still share
+        let pending: Vec<&mut SessionTransaction> = self.transactions
+            .iter_mut()
+            .filter(|transaction| {
+                transaction.receipt.block_number == Some(number) &&
+                    transaction.receipt.block_hash.is_none()
+            })
+            .map(Arc::make_mut)
+            .collect();
+        // Transactions carry the block env they ran in, the current one is used for empty blocks
+        let block_env = pending.first().map_or(env_block, |transaction| transaction.block.clone());
+
+        let transaction_hashes: Vec<H256> = pending
+            .iter()
+            .map(|transaction| transaction.transaction.hash)
+            .collect();
+        let hash = local_block_hash(parent_hash, number, block_env.timestamp.into(), &transaction_hashes);
+
+        let mut gas_used = Uint::zero();
+        let mut logs_bloom = Bloom::default();
+        for transaction in pending {
+            gas_used += transaction.receipt.gas_used.unwrap_or_default();
+            logs_bloom.accrue_bloom(&transaction.receipt.logs_bloom);
+            transaction.transaction.block_hash = Some(hash);
+            transaction.receipt.block_hash = Some(hash);
+            for log in &mut transaction.receipt.logs {
+                log.block_hash = Some(hash);
+            }
+        }
+
+        let block = Block {
+            hash: Some(hash),
+            parent_hash,
+            number: Some(number),
+            timestamp: block_env.timestamp.into(),
+            author: Some(b160_to_h160(block_env.coinbase)),
+            gas_used,
+            gas_limit: block_env.gas_limit.into(),
+            base_fee_per_gas: Some(block_env.basefee.into()),
+            logs_bloom: Some(logs_bloom),
+            transactions: transaction_hashes,
+            ..Default::default()
+        };
+        self.blocks.push(block.clone());
+
+        block
+    }
+
+    /// Hash of a block mined in this EVM or, up to the fork block, of the forked chain.
+    pub fn get_block_hash(&self, number: U64) -> H256 {
+        if let Some(block) = self.blocks.iter().rev().find(|block| block.number == Some(number)) {
+            return block.hash.unwrap_or_default();
+        }
+        if number.as_u64() > self.fork_block_number {
+            return H256::zero();
+        }
+        self.executor
+            .backend()
+            .block_hash(u256_to_ru256(Uint::from(number.as_u64())))
+            .map(|hash| H256::from(hash.0))
+            .unwrap_or_default()
+    }
+
+    /// Logs matching `filter`, in the format of `eth_getLogs`. Logs of blocks before the fork
+    /// block are fetched from the forked chain, the transactions of this EVM go in the fork block
+    /// and the ones after it. Collects the logs of this EVM and prepares the request for the ones
+    /// of the forked chain, which can then be sent without holding the EVM.
+    pub fn logs_query(&self, filter: Filter) -> Result<LogsQuery, EvmError> {
+        if let Some(block_hash) = filter.get_block_hash() {
+            if !self.blocks.iter().any(|block| block.hash == Some(block_hash)) {
+                return Ok(LogsQuery { logs: vec![], upstream: self.upstream_logs_request(filter)? });
+            }
+            let params = FilteredParams::new(Some(filter));
+            let logs = self
+                .session_logs()
+                .filter(|log| log.block_hash == Some(block_hash))
+                .filter(|log| params.filter_address(log) && params.filter_topics(log))
+                .cloned()
+                .collect();
+            return Ok(LogsQuery { logs, upstream: None });
+        }
+
+        let current = self.get_block().as_u64();
+        let resolve = |block: Option<&BlockNumber>| {
+            match block {
+                Some(BlockNumber::Number(number)) => number.as_u64(),
+                Some(BlockNumber::Earliest) => 0,
+                _ => current,
+            }
+        };
+        let from_block = resolve(filter.block_option.get_from_block());
+        let to_block = resolve(filter.block_option.get_to_block());
+
+        let upstream = if from_block < self.fork_block_number {
+            let upstream_filter = filter
+                .clone()
+                .from_block(from_block)
+                .to_block(to_block.min(self.fork_block_number - 1));
+            self.upstream_logs_request(upstream_filter)?
+        } else {
+            None
+        };
+
+        let params = FilteredParams::new(Some(filter.from_block(from_block).to_block(to_block)));
+        let logs = self
+            .session_logs()
+            .filter(|log| {
+                let block_number = log.block_number.unwrap_or_default().as_u64();
+                params.filter_block_range(block_number) &&
+                    params.filter_address(log) &&
+                    params.filter_topics(log)
+            })
+            .cloned()
+            .collect();
+
+        Ok(LogsQuery { logs, upstream })
+    }
+
+    fn session_logs(&self) -> impl Iterator<Item = &Log> {
+        self.transactions.iter().flat_map(|transaction| &transaction.receipt.logs)
+    }
+
+    fn upstream_logs_request(&self, filter: Filter) -> Result<Option<(Provider<Http>, Filter)>, EvmError> {
+        if self.is_offline() {
+            return Ok(None);
+        }
+        Ok(Some((self.upstream_provider()?, filter)))
+    }
+
+    /// Captures the backend, env, active fork and history so that they can be put back with
+    /// `restore`. Snapshots and atomic bundles are both built on checkpoints.
+    pub fn checkpoint(&self) -> EvmCheckpoint {
+        EvmCheckpoint {
+            backend: self.executor.backend().clone(),
+            env: self.executor.env().clone(),
+            fork_url: self.fork_url.clone(),
+            fork_block_number: self.fork_block_number,
+            transaction_count: self.transaction_count,
+            transactions: self.transactions.clone(),
+            blocks: self.blocks.clone(),
+            written_slots: self.written_slots.clone(),
+            time_offset: self.time_offset,
+            forks: self.forks.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, checkpoint: EvmCheckpoint) {
+        *self.executor.backend_mut() = checkpoint.backend;
+        *self.executor.env_mut() = checkpoint.env;
+        self.fork_url = checkpoint.fork_url;
+        self.fork_block_number = checkpoint.fork_block_number;
+        self.transaction_count = checkpoint.transaction_count;
+        self.transactions = checkpoint.transactions;
+        self.blocks = checkpoint.blocks;
+        self.written_slots = checkpoint.written_slots;
+        self.time_offset = checkpoint.time_offset;
+        self.forks = checkpoint.forks;
+    }
+
+    /// Takes a snapshot of the backend, block env and history, returns its id.
+    pub fn snapshot(&mut self) -> Uint {
+        let id = self.next_snapshot_id;
+        self.next_snapshot_id += Uint::one();
+
+        let checkpoint = self.checkpoint();
+        self.snapshots.insert(id, checkpoint);
+        id
+    }
+
+    /// Reverts to the snapshot with the given id, returns `false` if there is no such snapshot.
+    /// The snapshot is consumed unless `keep` is set, in which case it can be reverted to again.
+    /// Snapshots taken after it are removed, as the state they were taken of is gone.
+    pub fn revert(&mut self, id: Uint, keep: bool) -> bool {
+        let checkpoint = if keep { self.snapshots.get(&id).cloned() } else { self.snapshots.remove(&id) };
+        let Some(checkpoint) = checkpoint else {
+            return false;
+        };
+
+        self.restore(checkpoint);
+        self.snapshots.retain(|snapshot_id, _| *snapshot_id <= id);
+        true
+    }
+
     pub async fn set_block(&mut self, number: u64) -> Result<(), EvmError> {
         self.executor.env_mut().block.number = Uint::from(number).into();
         Ok(())
@@ -273,10 +781,369 @@ impl Evm {
         self.executor.env().block.timestamp.into()
     }
 
+    /// Moves the timestamp of the current block forward by `seconds`, returning the total time
+    /// added this way, like anvil's `evm_increaseTime`.
+    pub async fn increase_time(&mut self, seconds: u64) -> Result<u64, EvmError> {
+        let timestamp = self.get_block_timestamp().as_u64() + seconds;
+        self.set_block_timestamp(timestamp).await?;
+        self.time_offset += seconds;
+        Ok(self.time_offset)
+    }
+
+    /// Moves forward by `blocks` blocks of `block_time` seconds each, deriving the basefee of
+    /// every block with the EIP-1559 formula. `gas_used` is the gas used in the current block,
+    /// skipped blocks are assumed to be empty.
+    pub fn advance_blocks(&mut self, blocks: u64, block_time: u64, gas_used: u64) {
+        let env = self.executor.env_mut();
+        let gas_limit = Uint::from(env.block.gas_limit).low_u64();
+        let mut basefee = Uint::from(env.block.basefee);
+        for block in 0..blocks {
+            basefee = next_basefee(basefee, if block == 0 { gas_used } else { 0 }, gas_limit);
+        }
+
+        let number = Uint::from(env.block.number) + blocks;
+        let timestamp = Uint::from(env.block.timestamp) + blocks * block_time;
+        env.block.number = number.into();
+        env.block.timestamp = timestamp.into();
+        env.block.basefee = basefee.into();
+    }
+
     pub fn get_chain_id(&self) -> Uint {
         self.executor.env().cfg.chain_id.into()
     }
 
+    pub fn get_fork_url_alias(&self) -> String {
+        fork_url_alias(&self.fork_url)
+    }
+
+    pub fn get_coinbase(&self) -> Address {
+        b160_to_h160(self.executor.env().block.coinbase)
+    }
+
+    pub fn get_basefee(&self) -> Uint {
+        self.executor.env().block.basefee.into()
+    }
+
+    pub fn get_balance(&self, address: Address) -> Result<Uint, EvmError> {
+        let account = self.executor
+            .backend()
+            .basic(h160_to_b160(address))
+            .map_err(|err| EvmError(err.into()))?;
+        Ok(account.map(|info| info.balance.into()).unwrap_or_default())
+    }
+
+    pub fn get_nonce(&self, address: Address) -> Result<u64, EvmError> {
+        let account = self.executor
+            .backend()
+            .basic(h160_to_b160(address))
+            .map_err(|err| EvmError(err.into()))?;
+        Ok(account.map(|info| info.nonce).unwrap_or_default())
+    }
+
+    pub fn get_code(&self, address: Address) -> Result<Bytes, EvmError> {
+        let backend = self.executor.backend();
+        let Some(account) = backend
+            .basic(h160_to_b160(address))
+            .map_err(|err| EvmError(err.into()))? else {
+            return Ok(Bytes::default());
+        };
+        let code = match account.code {
+            Some(code) => code,
+            None => backend.code_by_hash(account.code_hash).map_err(|err| EvmError(err.into()))?,
+        };
+        Ok(code.original_bytes().into())
+    }
+
+    pub fn get_storage_at(&self, address: Address, slot: Uint) -> Result<Uint, EvmError> {
+        let value = self.executor
+            .backend()
+            .storage(h160_to_b160(address), u256_to_ru256(slot))
+            .map_err(|err| EvmError(err.into()))?;
+        Ok(value.into())
+    }
+
+    /// Serializes the accounts committed or overridden in this EVM and its block env, in the
+    /// format of anvil's `anvil_dumpState`. Only the storage slots that were written are included,
+    /// the others are still the ones of the fork.
+    pub fn dump_state(&self) -> SerializableState {
+        let backend = self.executor.backend();
+        let accounts = match backend.active_fork_db() {
+            Some(db) => dump_cache_db(db, &self.written_slots),
+            None => dump_cache_db(backend.mem_db(), &self.written_slots),
+        };
+
+        SerializableState {
+            block: Some(self.executor.env().block.clone()),
+            accounts,
+        }
+    }
+
+    /// Dumps the state of the forks of the other chains, selecting each of them in turn. The
+    /// active chain is selected again afterwards.
+    pub fn dump_forks(&mut self) -> Result<Vec<PersistedFork>, EvmError> {
+        let active_chain_id = self.get_chain_id().as_u64();
+        let chain_ids: Vec<u64> = self.forks.keys().copied().collect();
+
+        let mut forks = Vec::with_capacity(chain_ids.len());
+        for chain_id in chain_ids {
+            self.select_chain(chain_id)?;
+            forks.push(PersistedFork {
+                chain_id,
+                block_number: self.fork_block_number,
+                state: self.dump_state(),
+            });
+        }
+        self.select_chain(active_chain_id)?;
+
+        Ok(forks)
+    }
+
+    /// Applies a state in the format of anvil's `anvil_loadState`. The storage this EVM wrote to its
+    /// accounts is replaced by the one of the state: slots the state doesn't have go back to the
+    /// values they had before they were written.
+    pub fn load_state(&mut self, state: SerializableState) -> Result<(), OverrideError> {
+        for (address, account) in state.accounts {
+            let replaced: HashMap<Uint, Uint> = self.written_slots
+                .remove(&address)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(slot, _)| !account.storage.contains_key(slot))
+                .collect();
+            let slots = replaced
+                .iter()
+                .map(|(slot, original)| (*slot, *original))
+                .chain(account.storage)
+                .map(|(key, value)| {
+                    let mut slot = [0u8; 32];
+                    key.to_big_endian(&mut slot);
+                    (slot.into(), value)
+                })
+                .collect();
+
+            self.override_account(
+                address,
+                Some(account.balance),
+                Some(account.nonce),
+                Some(account.code),
+                Some(StorageOverride { slots, diff: true })
+            )?;
+        }
+
+        if let Some(block) = state.block {
+            self.executor.env_mut().block = block;
+        }
+
+        Ok(())
+    }
+
+    /// Re-points the fork to `block_number`, in the style of foundry's `rollFork`. The balances,
+    /// nonces, code and storage slots that were written keep their local values, everything else
+    /// is read from the new fork block. The block env becomes the one of the new fork block, and
+    /// the blocks of this EVM move along so that they still follow the fork block.
+    pub fn roll_fork(&mut self, block_number: u64) -> Result<(), EvmError> {
+        if self.is_offline() {
+            return Err(EvmError(eyre!("offline simulations have no fork to roll")));
+        }
+        let changes = match self.executor.backend().active_fork_db() {
+            Some(db) => changed_accounts(db, &self.written_slots),
+            None => vec![],
+        };
+        let offset = block_number - self.fork_block_number;
+        let current = self.get_block().as_u64();
+
+        let mut env = self.executor.env().clone();
+        self.executor
+            .backend_mut()
+            .roll_fork(
+                None,
+                u256_to_ru256(block_number.into()),
+                &mut env,
+                &mut JournaledState::new(0)
+            )
+            .map_err(EvmError)?;
+        env.block.number = Uint::from(current + offset).into();
+        *self.executor.env_mut() = env;
+        self.fork_block_number = block_number;
+
+        // Written slots are tracked again against the new fork block
+        self.written_slots.clear();
+        for change in changes {
+            self.override_account(
+                change.address,
+                change.balance,
+                change.nonce,
+                change.code,
+                Some(change.storage)
+            ).map_err(|_| {
+                EvmError(eyre!("failed to reapply the local state after rolling the fork"))
+            })?;
+        }
+        self.renumber_history(offset);
+
+        Ok(())
+    }
+
+    /// Moves the fork of `chain_id` to `upstream`, at the same fork block. The balances, nonces,
+    /// code and storage slots that were written keep their local values, everything else is read
+    /// from the new upstream. The block env and history don't change.
+    pub fn switch_upstream(&mut self, chain_id: u64, upstream: Arc<UpstreamLease>) -> Result<(), EvmError> {
+        let active_chain_id = self.get_chain_id().as_u64();
+        self.select_chain(chain_id)?;
+        let result = self.switch_active_upstream(upstream);
+        self.select_chain(active_chain_id)?;
+        result
+    }
+
+    fn switch_active_upstream(&mut self, upstream: Arc<UpstreamLease>) -> Result<(), EvmError> {
+        let fork_opts = create_fork_opts(&upstream, Some(self.fork_block_number)).map_err(|err| {
+            EvmError(eyre!("failed to fork the next upstream: {err:?}"))
+        })?;
+        let changes = match self.executor.backend().active_fork_db() {
+            Some(db) => changed_accounts(db, &self.written_slots),
+            None => vec![],
+        };
+
+        // Selecting the new fork overwrites the env with the one it was created with
+        let mut env = self.executor.env().clone();
+        let block = env.block.clone();
+        self.executor
+            .backend_mut()
+            .create_select_fork(fork_opts, &mut env, &mut JournaledState::new(0))
+            .map_err(EvmError)?;
+        env.block = block;
+        *self.executor.env_mut() = env;
+        // The lease of the previous upstream is kept, snapshots might still refer to its fork
+        self.fork_url = upstream.upstream_url().to_string();
+        self.upstreams.push(upstream);
+
+        // Written slots are tracked again against the new fork
+        self.written_slots.clear();
+        for change in changes {
+            self.override_account(
+                change.address,
+                change.balance,
+                change.nonce,
+                change.code,
+                Some(change.storage)
+            ).map_err(|_| {
+                EvmError(eyre!("failed to reapply the local state on the next upstream"))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Chain ids and upstream URLs of the forks whose upstream the upstream proxy gave up on, as
+    /// the next upstream of their chain might serve them.
+    pub fn failed_forks(&self) -> Vec<(u64, String)> {
+        let active = (self.get_chain_id().as_u64(), &self.fork_url);
+        let inactive = self.forks.iter().map(|(chain_id, fork)| (*chain_id, &fork.fork_url));
+        std::iter::once(active)
+            .chain(inactive)
+            .filter(|(_, fork_url)| {
+                self.upstreams.iter().any(|upstream| {
+                    upstream.upstream_url() == fork_url.as_str() &&
+                        matches!(
+                            upstream.failure(),
+                            Some(
+                                UpstreamFailure::RateLimited |
+                                    UpstreamFailure::Timeout |
+                                    UpstreamFailure::Unreachable
+                            )
+                        )
+                })
+            })
+            .map(|(chain_id, fork_url)| (chain_id, fork_url.clone()))
+            .collect()
+    }
+
+    /// Moves the transactions and mined blocks of this EVM `offset` blocks later, chaining the
+    /// mined blocks to the blocks of the fork again.
+    fn renumber_history(&mut self, offset: u64) {
+        if offset == 0 {
+            return;
+        }
+
+        let mut hashes = HashMap::new();
+        for index in 0..self.blocks.len() {
+            let number = self.blocks[index].number.unwrap_or_default() + offset;
+            // Blocks are mined in order, the parent of a block is renumbered before it
+            let parent_hash = self.get_block_hash(number.saturating_sub(1.into()));
+            let block = &mut self.blocks[index];
+            let hash = local_block_hash(parent_hash, number, block.timestamp, &block.transactions);
+            if let Some(previous) = block.hash.replace(hash) {
+                hashes.insert(previous, hash);
+            }
+            block.number = Some(number);
+            block.parent_hash = parent_hash;
+        }
+
+        let renumber = |number: &mut Option<U64>| {
+            if let Some(number) = number {
+                *number += U64::from(offset);
+            }
+        };
+        let rehash = |hash: &mut Option<H256>| {
+            if let Some(new_hash) = hash.and_then(|hash| hashes.get(&hash)) {
+                *hash = Some(*new_hash);
+            }
+        };
+        for transaction in &mut self.transactions {
+            let transaction = Arc::make_mut(transaction);
+            transaction.block.number += revm::primitives::U256::from(offset);
+            renumber(&mut transaction.transaction.block_number);
+            rehash(&mut transaction.transaction.block_hash);
+            renumber(&mut transaction.receipt.block_number);
+            rehash(&mut transaction.receipt.block_hash);
+            for log in &mut transaction.receipt.logs {
+                renumber(&mut log.block_number);
+                rehash(&mut log.block_hash);
+            }
+        }
+    }
+
+    pub async fn get_latest_block_number(&self) -> Result<u64, EvmError> {
+        if self.is_offline() {
+            return Ok(self.fork_block_number);
+        }
+        let provider = self.upstream_provider()?;
+        let block_number = provider.get_block_number().await.map_err(|err| EvmError(err.into()))?;
+        Ok(block_number.as_u64())
+    }
+
+    /// Provider for the upstream of the active fork, through the upstream proxy so that its
+    /// requests are subject to the same policy and cassette as the fork's.
+    fn upstream_provider(&self) -> Result<Provider<Http>, EvmError> {
+        let url = self.upstreams
+            .iter()
+            .find(|upstream| upstream.upstream_url() == self.fork_url)
+            .map_or_else(|| self.fork_url.clone(), |upstream| upstream.proxy_url());
+        Provider::<Http>::try_from(url.as_str()).map_err(|err| EvmError(err.into()))
+    }
+
+    /// Why the upstream proxy gave up on a request of one of the forks, if it did.
+    pub fn upstream_failure(&self) -> Option<UpstreamFailure> {
+        self.upstreams.iter().find_map(|upstream| upstream.failure())
+    }
+
+    /// Starts a new budget of upstream requests for all forks, done for every API request.
+    pub fn reset_upstream_usage(&self) {
+        for upstream in &self.upstreams {
+            upstream.reset();
+        }
+    }
+
+    /// Rough estimate in bytes of the memory held by the state of the active fork of this EVM,
+    /// including the accounts, storage and code cached from the upstream, which every EVM holds a
+    /// copy of.
+    pub fn approximate_memory_size(&self) -> usize {
+        let backend = self.executor.backend();
+        match backend.active_fork_db() {
+            Some(db) => cache_db_memory_size(db),
+            None => cache_db_memory_size(backend.mem_db()),
+        }
+    }
+
     fn set_access_list(&mut self, access_list: Option<AccessList>) {
         self.executor.env_mut().tx.access_list = access_list
             .unwrap_or_default()
@@ -293,3 +1160,289 @@ impl Evm {
             .collect();
     }
 }
+
+impl LogsQuery {
+    /// Fetches the logs of the forked chain, if any are needed, followed by the ones of the EVM.
+    pub async fn fetch(self) -> Result<Vec<Log>, EvmError> {
+        let mut logs = match self.upstream {
+            Some((provider, filter)) => {
+                provider.get_logs(&filter).await.map_err(|err| EvmError(err.into()))?
+            }
+            None => vec![],
+        };
+        logs.extend(self.logs);
+        Ok(logs)
+    }
+}
+
+/// Stands in for the fork URL of offline EVMs, it is what the `X-Upstream` header reports.
+pub const OFFLINE_FORK_URL: &str = "offline";
+
+/// Host of a fork URL, paths and credentials are left out as they often contain API keys.
+pub fn fork_url_alias(fork_url: &str) -> String {
+    let without_scheme = fork_url.split("://").last().unwrap_or_default();
+    let host = without_scheme.split(['/', '?']).next().unwrap_or_default();
+    host.rsplit('@').next().unwrap_or_default().to_string()
+}
+
+fn evm_opts(fork_url: Option<String>, fork_block_number: Option<u64>, chain_id: Option<u64>) -> EvmOpts {
+    EvmOpts {
+        fork_url,
+        fork_block_number,
+        env: foundry_evm::executor::opts::Env {
+            chain_id,
+            code_size_limit: None,
+            gas_price: Some(0),
+            gas_limit: u64::MAX,
+            ..Default::default()
+        },
+        memory_limit: foundry_config::Config::default().memory_limit,
+        ..Default::default()
+    }
+}
+
+/// Fork options for forking through `upstream`. foundry's block cache is left out when requests
+/// go to a cassette, as cached data would be missing from recordings.
+pub(crate) fn create_fork_opts(
+    upstream: &UpstreamLease,
+    fork_block_number: Option<u64>
+) -> Result<CreateFork, ForkError> {
+    let fork_url = upstream.proxy_url();
+    let evm_opts = evm_opts(Some(fork_url.clone()), fork_block_number, None);
+
+    let envi = evm_opts.evm_env_blocking().map_err(|err| {
+        let err = format!("{err:#}");
+        match missing_block(&err) {
+            Some(error) => error,
+            None => {
+                log::warn!(target: "ts::api", "Failed to instantiate forked environment: {err}");
+                ForkError::Failed
+            }
+        }
+    })?;
+
+    Ok(CreateFork {
+        url: fork_url,
+        enable_caching: !upstream.uses_cassette(),
+        env: envi,
+        evm_opts,
+    })
+}
+
+/// foundry fails to fork a block the upstream has no header of with `Failed to get block for block
+/// number: {number}`, followed by `latest block number: {latest}` when the upstream is reachable.
+/// Blocks up to the latest one exist, so it's the upstream that doesn't keep them.
+fn missing_block(err: &str) -> Option<ForkError> {
+    let (_, rest) = err.split_once("Failed to get block for block number: ")?;
+    let number = |value: &str| {
+        value.chars().take_while(char::is_ascii_digit).collect::<String>().parse::<u64>().ok()
+    };
+    let latest = rest.split_once("latest block number: ").and_then(|(_, latest)| number(latest));
+
+    match (number(rest), latest) {
+        (Some(block), Some(latest)) if block <= latest => Some(ForkError::HistoricalStateUnavailable),
+        _ => Some(ForkError::BlockNotFound),
+    }
+}
+
+/// Errors of upstreams that don't keep the state of older blocks, as reported by common clients.
+const HISTORICAL_STATE_ERRORS: [&str; 5] = [
+    "missing trie node",
+    "historical state",
+    "state is not available",
+    "state not available",
+    "pruned",
+];
+
+impl EvmError {
+    /// Whether the upstream failed to serve fork data because it doesn't keep the state of the
+    /// fork block.
+    pub fn is_historical_state_unavailable(&self) -> bool {
+        let err = format!("{:#}", self.0).to_lowercase();
+        HISTORICAL_STATE_ERRORS.iter().any(|message| err.contains(message))
+    }
+}
+
+fn build_decoder(
+    chain: Chain,
+    etherscan_key: Option<String>
+) -> (CallTraceDecoder, Option<EtherscanIdentifier>) {
+    let foundry_config = foundry_config::Config {
+        etherscan_api_key: etherscan_key,
+        ..Default::default()
+    };
+
+    let etherscan_identifier = EtherscanIdentifier::new(&foundry_config, Some(chain)).ok();
+    let mut decoder = CallTraceDecoderBuilder::new().with_verbosity(5).build();
+
+    if
+        let Ok(identifier) = SignaturesIdentifier::new(
+            foundry_config::Config::foundry_cache_dir(),
+            false
+        )
+    {
+        decoder.add_signature_identifier(identifier);
+    }
+
+    (decoder, etherscan_identifier)
+}
+
+/// Accounts of `db` that were committed to, with the slots of `written_slots`, or all of their slots
+/// if their storage was cleared. Slots that were only read are cached in `db` as well, but they are
+/// left out as they are the ones of the fork.
+fn dump_cache_db<ExtDB>(
+    db: &CacheDB<ExtDB>,
+    written_slots: &WrittenSlots
+) -> BTreeMap<Address, SerializableAccountRecord> {
+    db.accounts
+        .iter()
+        .filter(|(_, account)| {
+            matches!(account.account_state, AccountState::Touched | AccountState::StorageCleared)
+        })
+        .map(|(address, account)| {
+            let code = account.info.code
+                .clone()
+                .or_else(|| db.contracts.get(&account.info.code_hash).cloned())
+                .map(|code| code.original_bytes().into())
+                .unwrap_or_default();
+            let cleared = matches!(account.account_state, AccountState::StorageCleared);
+            let written = written_slots.get(&b160_to_h160(*address));
+            let storage = account.storage
+                .iter()
+                .map(|(key, value)| (Uint::from(*key), Uint::from(*value)))
+                .filter(|(key, _)| {
+                    cleared || written.map_or(false, |written| written.contains_key(key))
+                })
+                .collect();
+
+            let record = SerializableAccountRecord {
+                nonce: account.info.nonce,
+                balance: account.info.balance.into(),
+                code,
+                storage,
+            };
+            (b160_to_h160(*address), record)
+        })
+        .collect()
+}
+
+/// Changes of the accounts of a fork's `db` compared to the fork, as overrides: the balance, nonce
+/// and code when they differ from the ones of the fork, and the slots of `written_slots`, or all of
+/// the slots of the accounts whose storage was cleared.
+fn changed_accounts<ExtDB: DatabaseRef>(
+    db: &CacheDB<ExtDB>,
+    written_slots: &WrittenSlots
+) -> Vec<AccountChanges> {
+    db.accounts
+        .iter()
+        .filter(|(_, account)| {
+            matches!(account.account_state, AccountState::Touched | AccountState::StorageCleared)
+        })
+        .filter_map(|(address, account)| {
+            let info = &account.info;
+            let info_changed = db.db.basic(*address).ok().flatten().map_or(true, |fork_info| {
+                fork_info.balance != info.balance ||
+                    fork_info.nonce != info.nonce ||
+                    fork_info.code_hash != info.code_hash
+            });
+            let cleared = matches!(account.account_state, AccountState::StorageCleared);
+            let written = written_slots.get(&b160_to_h160(*address));
+            let slots: HashMap<H256, Uint> = account.storage
+                .iter()
+                .filter(|(key, _)| {
+                    cleared || written.map_or(false, |written| written.contains_key(&ru256_to_u256(**key)))
+                })
+                .map(|(key, value)| (H256::from(key.to_be_bytes::<32>()), ru256_to_u256(*value)))
+                .collect();
+            if !info_changed && !cleared && slots.is_empty() {
+                return None;
+            }
+
+            let code = info.code
+                .clone()
+                .or_else(|| db.contracts.get(&info.code_hash).cloned())
+                .map(|code| code.original_bytes().into());
+            Some(AccountChanges {
+                address: b160_to_h160(*address),
+                balance: info_changed.then(|| ru256_to_u256(info.balance)),
+                nonce: info_changed.then_some(info.nonce),
+                code: if info_changed { code } else { None },
+                storage: StorageOverride { slots, diff: !cleared },
+            })
+        })
+        .collect()
+}
+
+/// Hash of a block mined in an EVM, derived from its parent, number, timestamp and transactions.
+fn local_block_hash(parent_hash: H256, number: U64, timestamp: Uint, transaction_hashes: &[H256]) -> H256 {
+    let mut data = [parent_hash.as_bytes(), &number.as_u64().to_be_bytes()].concat();
+    let mut timestamp_bytes = [0u8; 32];
+    timestamp.to_big_endian(&mut timestamp_bytes);
+    data.extend_from_slice(&timestamp_bytes);
+    for hash in transaction_hashes {
+        data.extend_from_slice(hash.as_bytes());
+    }
+    H256::from(keccak256(data))
+}
+
+fn cache_db_memory_size<ExtDB>(db: &CacheDB<ExtDB>) -> usize {
+    const ACCOUNT_SIZE: usize = 128;
+    const STORAGE_SLOT_SIZE: usize = 64;
+
+    let accounts: usize = db.accounts
+        .values()
+        .map(|account| ACCOUNT_SIZE + account.storage.len() * STORAGE_SLOT_SIZE)
+        .sum();
+    let contracts: usize = db.contracts.values().map(|code| code.len()).sum();
+
+    accounts + contracts
+}
+
+fn next_basefee(basefee: Uint, gas_used: u64, gas_limit: u64) -> Uint {
+    const ELASTICITY_MULTIPLIER: u64 = 2;
+    const BASEFEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+
+    let gas_target = gas_limit / ELASTICITY_MULTIPLIER;
+    if gas_target == 0 || gas_used == gas_target {
+        return basefee;
+    }
+
+    if gas_used > gas_target {
+        let delta = basefee * (gas_used - gas_target) / gas_target / BASEFEE_MAX_CHANGE_DENOMINATOR;
+        basefee + delta.max(Uint::one())
+    } else {
+        let delta = basefee * (gas_target - gas_used) / gas_target / BASEFEE_MAX_CHANGE_DENOMINATOR;
+        basefee.saturating_sub(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::Uint;
+
+    use crate::structs::ForkError;
+
+    #[test]
+    fn test_next_basefee() {
+        let basefee = Uint::from(1_000_000_000u64);
+
+        assert_eq!(super::next_basefee(basefee, 15_000_000, 30_000_000), basefee);
+        assert_eq!(super::next_basefee(basefee, 30_000_000, 30_000_000), Uint::from(1_125_000_000u64));
+        assert_eq!(super::next_basefee(basefee, 0, 30_000_000), Uint::from(875_000_000u64));
+        assert_eq!(super::next_basefee(Uint::from(7), 15_000_001, 30_000_000), Uint::from(8));
+    }
+
+    #[test]
+    fn test_missing_block() {
+        let err = "Could not instantiate forked environment: Failed to get block for block number: 20000000\nlatest block number: 19000000";
+        assert_eq!(super::missing_block(err), Some(ForkError::BlockNotFound));
+
+        let err = "Failed to get block for block number: 1000\nlatest block number: 19000000";
+        assert_eq!(super::missing_block(err), Some(ForkError::HistoricalStateUnavailable));
+
+        let err = "Failed to get block for block number: 1000";
+        assert_eq!(super::missing_block(err), Some(ForkError::BlockNotFound));
+
+        assert_eq!(super::missing_block("error sending request"), None);
+    }
+}
@@ -1,8 +1,25 @@
 use dashmap::DashMap;
 use structs::Evm;
 use serde::de::DeserializeOwned;
-use structs::{SimulationRequest, StatefulSimulationRequest};
+use structs::{
+    SessionInfo,
+    SessionStore,
+    UnauthorizedError,
+    CallBundleRequest,
+    SimulationRequest,
+    SimulationBundleRequest,
+    StatefulSimulationRequest,
+    StatefulSimulationLoadRequest,
+    StatefulSimulationRevertRequest,
+    StatefulSimulationStateRequest,
+    StatefulSimulationRollForkRequest,
+    PermissiveUint,
+    JsonRpcPayload,
+};
+use ethers::abi::Address;
+use ethers::types::Filter as LogFilter;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 use warp::{Filter, Rejection, Reply};
@@ -19,11 +36,23 @@ pub mod structs;
 pub mod errors;
 pub mod evm;
 
+pub mod chains;
 pub mod simulation;
+pub mod rpc;
+pub mod session;
+pub mod session_store;
+pub mod upstream;
+pub mod offline;
+pub mod cassette;
+pub mod backend_pool;
+pub mod workers;
 
-
+#[derive(Default)]
 pub struct SharedSimulationState {
     pub evms: Arc<DashMap<Uuid, Arc<Mutex<Evm>>>>,
+    pub sessions: Arc<DashMap<Uuid, SessionInfo>>,
+    pub expired_sessions: Arc<DashMap<Uuid, Instant>>,
+    pub store: Option<SessionStore>,
 }
 
 pub fn simulate_routes(
@@ -33,12 +62,33 @@ pub fn simulate_routes(
     let config_ref = &config;
     simulate(config_ref.clone())
         .or(simulate_bundle(config_ref.clone()))
+        .or(call_bundle(config_ref.clone()))
         .or(simulate_stateful_new(
             config_ref.clone(),
             Arc::clone(&state),
         ))
+        .or(simulate_stateful_load(
+            config_ref.clone(),
+            Arc::clone(&state),
+        ))
         .or(simulate_stateful_end(Arc::clone(&state)))
-        .or(simulate_stateful(config, Arc::clone(&state)))
+        .or(simulate_stateful_dump(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_clone(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_info(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_list(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_snapshot(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_revert(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_state(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_roll_fork(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_account(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_storage(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_call(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_transactions(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_mine(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful_logs(config_ref.clone(), Arc::clone(&state)))
+        .or(simulate_stateful(config_ref.clone(), Arc::clone(&state)))
+        .or(rpc(config_ref.clone(), Arc::clone(&state)))
+        .or(chains_route(config))
         .or(index_route())
         .or(status_route()) 
         .or(version_route())
@@ -46,11 +96,32 @@ pub fn simulate_routes(
 
 /// POST /simulate
 pub fn simulate(config: Config) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
     warp::path!("simulate")
         .and(warp::post())
         .and(json_body::<SimulationRequest>(&config))
         .and(with_config(config))
-        .and_then(simulation::simulate)
+        .and_then(move |transaction: SimulationRequest, config: Config| {
+            workers.run(simulation::simulate(transaction, config))
+        })
+}
+
+/// POST /rpc/{statefulSimulationId}
+pub fn rpc(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("rpc" / Uuid)
+        .and(warp::post())
+        .and(json_body::<JsonRpcPayload>(&config))
+        .and(with_config(config))
+        .and(with_state(state))
+        .and_then(move |param: Uuid, payload: JsonRpcPayload, config: Config, state: Arc<SharedSimulationState>| {
+            workers.run_in_session(state.get_session(&param), move |evm| {
+                rpc::rpc(param, evm, payload, config, state)
+            })
+        })
 }
 
 /// GET index
@@ -66,11 +137,28 @@ fn index_route() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Cl
 pub fn simulate_bundle(
     config: Config,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
     warp::path!("simulate-bundle")
         .and(warp::post())
-        .and(json_body(&config))
+        .and(json_body::<SimulationBundleRequest>(&config))
+        .and(with_config(config))
+        .and_then(move |bundle: SimulationBundleRequest, config: Config| {
+            workers.run(simulation::simulate_bundle(bundle, config))
+        })
+}
+
+/// POST /call-bundle
+pub fn call_bundle(
+    config: Config,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("call-bundle")
+        .and(warp::post())
+        .and(json_body::<CallBundleRequest>(&config))
         .and(with_config(config))
-        .and_then(simulation::simulate_bundle)
+        .and_then(move |request: CallBundleRequest, config: Config| {
+            workers.run(simulation::call_bundle(request, config))
+        })
 }
 
 /// POST /simulate-stateful
@@ -78,12 +166,77 @@ pub fn simulate_stateful_new(
     config: Config,
     state: Arc<SharedSimulationState>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
     warp::path!("simulate-stateful")
         .and(warp::post())
         .and(json_body::<StatefulSimulationRequest>(&config))
+        .and(warp::header::optional::<String>("X-API-KEY"))
+        .and(with_config(config))
+        .and(with_state(state))
+        .and_then(
+            move |request: StatefulSimulationRequest,
+                  api_key: Option<String>,
+                  config: Config,
+                  state: Arc<SharedSimulationState>| {
+                workers.run(simulation::simulate_stateful_new(request, api_key, config, state))
+            },
+        )
+}
+
+/// POST /simulate-stateful/load
+pub fn simulate_stateful_load(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / "load")
+        .and(warp::post())
+        .and(json_body::<StatefulSimulationLoadRequest>(&config))
+        .and(warp::header::optional::<String>("X-API-KEY"))
+        .and(with_config(config))
+        .and(with_state(state))
+        .and_then(
+            move |request: StatefulSimulationLoadRequest,
+                  api_key: Option<String>,
+                  config: Config,
+                  state: Arc<SharedSimulationState>| {
+                workers.run(simulation::simulate_stateful_load(request, api_key, config, state))
+            },
+        )
+}
+
+/// GET /simulate-stateful/{statefulSimulationId}/dump
+pub fn simulate_stateful_dump(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "dump")
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(move |param: Uuid, state: Arc<SharedSimulationState>| {
+            workers.run_in_session(state.get_session(&param), simulation::simulate_stateful_dump)
+        })
+}
+
+/// POST /simulate-stateful/{statefulSimulationId}/clone
+pub fn simulate_stateful_clone(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "clone")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("X-API-KEY"))
         .and(with_config(config))
         .and(with_state(state))
-        .and_then(simulation::simulate_stateful_new)
+        .and_then(
+            move |param: Uuid, api_key: Option<String>, config: Config, state: Arc<SharedSimulationState>| {
+                workers.run_in_session(state.get_session(&param), move |evm| {
+                    simulation::simulate_stateful_clone(evm, api_key, config, state)
+                })
+            },
+        )
 }
 
 /// DELETE /simulate-stateful/{statefulSimulationId}
@@ -96,16 +249,232 @@ pub fn simulate_stateful_end(
         .and_then(simulation::simulate_stateful_end)
 }
 
+/// GET /simulate-stateful/{statefulSimulationId}
+pub fn simulate_stateful_info(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid)
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(move |param: Uuid, state: Arc<SharedSimulationState>| {
+            // Looking at a simulation doesn't count as using it
+            let session = state.peek_session(&param).map(|(evm, _)| evm);
+            workers.run_in_session(session, move |evm| simulation::simulate_stateful_info(param, evm, state))
+        })
+}
+
+/// GET /simulate-stateful
+pub fn simulate_stateful_list(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("simulate-stateful")
+        .and(warp::get())
+        .and(with_admin_key(config.admin_api_key))
+        .and(with_state(state))
+        .and_then(simulation::simulate_stateful_list)
+}
+
+/// POST /simulate-stateful/{statefulSimulationId}/snapshot
+pub fn simulate_stateful_snapshot(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "snapshot")
+        .and(warp::post())
+        .and(with_state(state))
+        .and_then(move |param: Uuid, state: Arc<SharedSimulationState>| {
+            workers.run_in_session(state.get_session(&param), simulation::simulate_stateful_snapshot)
+        })
+}
+
+/// POST /simulate-stateful/{statefulSimulationId}/revert
+pub fn simulate_stateful_revert(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "revert")
+        .and(warp::post())
+        .and(json_body::<StatefulSimulationRevertRequest>(&config))
+        .and(with_state(state))
+        .and_then(
+            move |param: Uuid, request: StatefulSimulationRevertRequest, state: Arc<SharedSimulationState>| {
+                workers.run_in_session(state.get_session(&param), move |evm| {
+                    simulation::simulate_stateful_revert(param, evm, request, state)
+                })
+            },
+        )
+}
+
+/// POST /simulate-stateful/{statefulSimulationId}/state
+pub fn simulate_stateful_state(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "state")
+        .and(warp::post())
+        .and(json_body::<StatefulSimulationStateRequest>(&config))
+        .and(with_state(state))
+        .and_then(
+            move |param: Uuid, request: StatefulSimulationStateRequest, state: Arc<SharedSimulationState>| {
+                workers.run_in_session(state.get_session(&param), move |evm| {
+                    simulation::simulate_stateful_state(param, evm, request, state)
+                })
+            },
+        )
+}
+
+/// POST /simulate-stateful/{statefulSimulationId}/roll-fork
+pub fn simulate_stateful_roll_fork(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "roll-fork")
+        .and(warp::post())
+        .and(json_body::<StatefulSimulationRollForkRequest>(&config))
+        .and(with_state(state))
+        .and_then(
+            move |param: Uuid, request: StatefulSimulationRollForkRequest, state: Arc<SharedSimulationState>| {
+                workers.run_in_session(state.get_session(&param), move |evm| {
+                    simulation::simulate_stateful_roll_fork(param, evm, request, state)
+                })
+            },
+        )
+}
+
+/// GET /simulate-stateful/{statefulSimulationId}/account/{address}
+pub fn simulate_stateful_account(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "account" / Address)
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(move |param: Uuid, address: Address, state: Arc<SharedSimulationState>| {
+            workers.run_in_session(state.get_session(&param), move |evm| {
+                simulation::simulate_stateful_account(evm, address)
+            })
+        })
+}
+
+/// GET /simulate-stateful/{statefulSimulationId}/storage/{address}/{slot}
+pub fn simulate_stateful_storage(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "storage" / Address / PermissiveUint)
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(
+            move |param: Uuid, address: Address, slot: PermissiveUint, state: Arc<SharedSimulationState>| {
+                workers.run_in_session(state.get_session(&param), move |evm| {
+                    simulation::simulate_stateful_storage(evm, address, slot)
+                })
+            },
+        )
+}
+
+/// POST /simulate-stateful/{statefulSimulationId}/call
+pub fn simulate_stateful_call(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "call")
+        .and(warp::post())
+        .and(json_body::<SimulationRequest>(&config))
+        .and(with_config(config))
+        .and(with_state(state))
+        .and_then(
+            move |param: Uuid, transaction: SimulationRequest, config: Config, state: Arc<SharedSimulationState>| {
+                workers.run_in_session(state.get_session(&param), move |evm| {
+                    simulation::simulate_stateful_call(evm, transaction, config)
+                })
+            },
+        )
+}
+
+/// GET /simulate-stateful/{statefulSimulationId}/transactions
+pub fn simulate_stateful_transactions(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "transactions")
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(move |param: Uuid, state: Arc<SharedSimulationState>| {
+            workers.run_in_session(state.get_session(&param), simulation::simulate_stateful_transactions)
+        })
+}
+
+/// POST /simulate-stateful/{statefulSimulationId}/mine
+pub fn simulate_stateful_mine(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "mine")
+        .and(warp::post())
+        .and(with_config(config))
+        .and(with_state(state))
+        .and_then(move |param: Uuid, config: Config, state: Arc<SharedSimulationState>| {
+            workers.run_in_session(state.get_session(&param), move |evm| {
+                simulation::simulate_stateful_mine(param, evm, config, state)
+            })
+        })
+}
+
+/// POST /simulate-stateful/{statefulSimulationId}/logs
+pub fn simulate_stateful_logs(
+    config: Config,
+    state: Arc<SharedSimulationState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
+    warp::path!("simulate-stateful" / Uuid / "logs")
+        .and(warp::post())
+        .and(json_body::<LogFilter>(&config))
+        .and(with_state(state))
+        .and_then(move |param: Uuid, filter: LogFilter, state: Arc<SharedSimulationState>| {
+            workers.run_in_session(state.get_session(&param), move |evm| {
+                simulation::simulate_stateful_logs(evm, filter)
+            })
+        })
+}
+
 /// POST /simulate-stateful/{statefulSimulationId}
 pub fn simulate_stateful(
     config: Config,
     state: Arc<SharedSimulationState>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let workers = Arc::clone(&config.simulation_workers);
     warp::path!("simulate-stateful" / Uuid)
         .and(warp::post())
-        .and(json_body(&config))
+        .and(json_body::<SimulationBundleRequest>(&config))
+        .and(with_config(config))
         .and(with_state(state))
-        .and_then(simulation::simulate_stateful)
+        .and_then(
+            move |param: Uuid, bundle: SimulationBundleRequest, config: Config, state: Arc<SharedSimulationState>| {
+                workers.run_in_session(state.get_session(&param), move |evm| {
+                    simulation::simulate_stateful(param, evm, bundle, config, state)
+                })
+            },
+        )
+}
+
+/// GET /chains
+fn chains_route(config: Config) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("chains")
+        .and(warp::get())
+        .map(move || warp::reply::json(&config.chains.list()))
 }
 
 /// GET /status
@@ -141,6 +510,50 @@ fn with_state(
     warp::any().map(move || state.clone())
 }
 
+/// Requests need a `X-API-KEY` header matching one of the comma-separated keys of `api_key`. Each
+/// client can have its own key, which stateful simulations are counted by for their limits.
+pub fn with_api_key(
+    api_key: &str,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let api_keys: Arc<Vec<String>> = Arc::new(
+        api_key
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(String::from)
+            .collect(),
+    );
+    warp::header::optional::<String>("X-API-KEY")
+        .and_then(move |key: Option<String>| {
+            let api_keys = Arc::clone(&api_keys);
+            async move {
+                match key {
+                    Some(key) if api_keys.contains(&key) => Ok(()),
+                    _ => Err(warp::reject::custom(UnauthorizedError)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Admin routes need the `X-ADMIN-KEY` header and are disabled if no admin key is configured.
+fn with_admin_key(
+    admin_api_key: Option<String>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("X-ADMIN-KEY")
+        .and_then(move |key: Option<String>| {
+            let admin_api_key = admin_api_key.clone();
+            async move {
+                match admin_api_key {
+                    Some(admin_api_key) if key.as_ref() == Some(&admin_api_key) => Ok(()),
+                    Some(_) => Err(warp::reject::custom(UnauthorizedError)),
+                    None => Err(warp::reject::not_found()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
 fn json_body<T: DeserializeOwned + Send>(
     config: &Config,
 ) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
@@ -1,11 +1,11 @@
 use std::{env, sync::Arc};
 
-use dashmap::DashMap;
-
 use symunix::{
     config::config,
     errors::handle_rejection,
+    session::reap_sessions,
     simulate_routes,
+    with_api_key,
     SharedSimulationState,
 };
 use warp::Filter;
@@ -28,14 +28,13 @@ async fn main() {
         .as_ref()
         .map(|key| {
             log::info!(target: "ts::api", "Running with API key protection");
-            let api_key_filter = warp::header::exact("X-API-KEY", Box::leak(key.clone().into_boxed_str()));
-            api_base.and(api_key_filter).boxed()
+            api_base.and(with_api_key(key)).boxed()
         })
         .unwrap_or_else(|| api_base.boxed());
 
-    let shared_state = Arc::new(SharedSimulationState {
-        evms: Arc::new(DashMap::new()),
-    });
+    let shared_state = Arc::new(SharedSimulationState::new(config_ref));
+    shared_state.restore_sessions(config_ref).await;
+    tokio::spawn(reap_sessions(shared_state.clone(), config_ref.clone()));
 
     let routes = api_base
         .and(simulate_routes(config_ref.clone(), shared_state.clone()))
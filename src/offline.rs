@@ -0,0 +1,76 @@
+use std::fs;
+
+use ethers::abi::Uint;
+use foundry_evm::utils::h160_to_b160;
+use revm::primitives::BlockEnv;
+use serde_json::Value;
+
+use crate::structs::{ Genesis, OfflineState, SerializableAccountRecord, SerializableState };
+
+impl OfflineState {
+    /// Reads a genesis file or a state dump of `GET /simulate-stateful/{id}/dump`. Dumps have no
+    /// chain id, they are on chain 1 unless the file has a `chainId` field.
+    pub fn load_file(path: &str) -> eyre::Result<Self> {
+        let value: Value = serde_json::from_slice(&fs::read(path)?)?;
+
+        if value.get("alloc").is_some() {
+            let genesis: Genesis = serde_json::from_value(value)?;
+            return Ok(genesis.into());
+        }
+
+        let chain_id = value.get("chainId").and_then(Value::as_u64).unwrap_or(1);
+        let state: SerializableState = serde_json::from_value(value)?;
+        Ok(OfflineState { chain_id, state })
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.state.block.as_ref().map_or(0, |block| block.number.to())
+    }
+}
+
+impl From<Genesis> for OfflineState {
+    fn from(genesis: Genesis) -> Self {
+        let mut block = BlockEnv::default();
+        if let Some(number) = genesis.number {
+            block.number = Uint::from(number).into();
+        }
+        if let Some(timestamp) = genesis.timestamp {
+            block.timestamp = Uint::from(timestamp).into();
+        }
+        if let Some(gas_limit) = genesis.gas_limit {
+            block.gas_limit = Uint::from(gas_limit).into();
+        }
+        if let Some(basefee) = genesis.base_fee_per_gas {
+            block.basefee = Uint::from(basefee).into();
+        }
+        if let Some(difficulty) = genesis.difficulty {
+            block.difficulty = Uint::from(difficulty).into();
+        }
+        if let Some(coinbase) = genesis.coinbase {
+            block.coinbase = h160_to_b160(coinbase);
+        }
+
+        let accounts = genesis.alloc
+            .into_iter()
+            .map(|(address, account)| {
+                let record = SerializableAccountRecord {
+                    nonce: account.nonce.map_or(0, |nonce| Uint::from(nonce).low_u64()),
+                    balance: account.balance.map(Uint::from).unwrap_or_default(),
+                    code: account.code,
+                    storage: account.storage
+                        .into_iter()
+                        .map(|(key, value)| {
+                            (Uint::from_big_endian(key.as_bytes()), Uint::from_big_endian(value.as_bytes()))
+                        })
+                        .collect(),
+                };
+                (address, record)
+            })
+            .collect();
+
+        OfflineState {
+            chain_id: genesis.config.chain_id,
+            state: SerializableState { block: Some(block), accounts },
+        }
+    }
+}
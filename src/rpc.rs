@@ -0,0 +1,382 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use ethers::abi::{ Address, Uint };
+use ethers::types::{ BlockNumber, Bytes, H256, U64 };
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::OwnedMutexGuard;
+use uuid::Uuid;
+use warp::reply::Json;
+use warp::Rejection;
+
+use crate::structs::{
+    CallRawRequest,
+    CallRawResult,
+    Config,
+    Evm,
+    EvmError,
+    JsonRpcError,
+    JsonRpcPayload,
+    JsonRpcRequest,
+    JsonRpcResponse,
+    OverrideError,
+    PendingRpcResponse,
+    PermissiveUint,
+    RpcTransactionRequest,
+    StorageOverride,
+};
+use crate::simulation::fail_over_session;
+use crate::SharedSimulationState;
+
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+const SERVER_ERROR: i64 = -32000;
+const EXECUTION_REVERTED: i64 = 3;
+
+/// Answers JSON-RPC requests, single or batched, against the EVM of a stateful simulation.
+pub async fn rpc(
+    param: Uuid,
+    mut evm: OwnedMutexGuard<Evm>,
+    payload: JsonRpcPayload,
+    config: Config,
+    state: Arc<SharedSimulationState>
+) -> Result<Json, Rejection> {
+    evm.reset_upstream_usage();
+
+    let (requests, batch) = match payload {
+        JsonRpcPayload::Single(request) => (vec![request], false),
+        JsonRpcPayload::Batch(requests) => (requests, true),
+    };
+    let mutated = requests.iter().any(|request| mutates_state(&request.method));
+    let mut pending = Vec::with_capacity(requests.len());
+    for request in requests {
+        pending.push(handle_request(&mut evm, &config, request).await);
+    }
+
+    // Later requests of the session run on the next upstream of the chain
+    fail_over_session(&mut evm, &config);
+    if mutated {
+        state.persist_session(&param, &mut evm);
+    }
+    drop(evm);
+
+    let mut responses = Vec::with_capacity(pending.len());
+    for response in pending {
+        responses.push(response.into_response().await);
+    }
+
+    if batch {
+        Ok(warp::reply::json(&responses))
+    } else {
+        Ok(warp::reply::json(&responses[0]))
+    }
+}
+
+fn mutates_state(method: &str) -> bool {
+    matches!(
+        method,
+        "eth_sendTransaction" |
+            "anvil_setBalance" |
+            "anvil_setCode" |
+            "anvil_setNonce" |
+            "anvil_setStorageAt" |
+            "hardhat_setBalance" |
+            "hardhat_setCode" |
+            "hardhat_setNonce" |
+            "hardhat_setStorageAt" |
+            "evm_mine" |
+            "evm_increaseTime" |
+            "evm_setNextBlockTimestamp" |
+            "evm_revert"
+    )
+}
+
+async fn handle_request(evm: &mut Evm, config: &Config, request: JsonRpcRequest) -> PendingRpcResponse {
+    // The logs of the forked chain are fetched without holding the simulation
+    if request.method == "eth_getLogs" {
+        let query = param(&request.params, 0).and_then(|filter| evm.logs_query(filter).map_err(server_error));
+        return match query {
+            Ok(query) => PendingRpcResponse::Logs(request.id, query),
+            Err(error) => PendingRpcResponse::Ready(response(request.id, Err(error))),
+        };
+    }
+
+    let result = dispatch(evm, config, &request.method, &request.params).await;
+    PendingRpcResponse::Ready(response(request.id, result))
+}
+
+impl PendingRpcResponse {
+    async fn into_response(self) -> JsonRpcResponse {
+        match self {
+            PendingRpcResponse::Ready(response) => response,
+            PendingRpcResponse::Logs(id, query) => {
+                let result = query.fetch().await.map_err(server_error).and_then(to_result);
+                response(id, result)
+            }
+        }
+    }
+}
+
+fn response(id: Value, result: Result<Value, JsonRpcError>) -> JsonRpcResponse {
+    let (result, error) = match result {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+
+    JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result,
+        error,
+    }
+}
+
+async fn dispatch(
+    evm: &mut Evm,
+    config: &Config,
+    method: &str,
+    params: &Value
+) -> Result<Value, JsonRpcError> {
+    match method {
+        "eth_chainId" => to_result(U64::from(evm.get_chain_id().as_u64())),
+        "eth_blockNumber" => to_result(U64::from(evm.get_block().as_u64())),
+        "eth_getBalance" => {
+            let address: Address = param(params, 0)?;
+            check_block(evm, param(params, 1)?)?;
+            to_result(evm.get_balance(address).map_err(server_error)?)
+        }
+        "eth_getCode" => {
+            let address: Address = param(params, 0)?;
+            check_block(evm, param(params, 1)?)?;
+            to_result(evm.get_code(address).map_err(server_error)?)
+        }
+        "eth_getStorageAt" => {
+            let address: Address = param(params, 0)?;
+            let slot: Uint = param(params, 1)?;
+            check_block(evm, param(params, 2)?)?;
+            let value = evm.get_storage_at(address, slot).map_err(server_error)?;
+            let mut bytes = [0u8; 32];
+            value.to_big_endian(&mut bytes);
+            to_result(H256::from(bytes))
+        }
+        "eth_getTransactionCount" => {
+            let address: Address = param(params, 0)?;
+            check_block(evm, param(params, 1)?)?;
+            to_result(Uint::from(evm.get_nonce(address).map_err(server_error)?))
+        }
+        "eth_call" => {
+            let transaction: RpcTransactionRequest = param(params, 0)?;
+            check_block(evm, param(params, 1)?)?;
+            let result = evm.call_raw(call_request(transaction)?).await.map_err(server_error)?;
+            check_reverted(&result)?;
+            to_result(result.return_data)
+        }
+        "eth_estimateGas" => {
+            let transaction: RpcTransactionRequest = param(params, 0)?;
+            let result = evm.call_raw(call_request(transaction)?).await.map_err(server_error)?;
+            check_reverted(&result)?;
+            to_result(U64::from(result.gas_used))
+        }
+        "eth_sendTransaction" => {
+            let transaction: RpcTransactionRequest = param(params, 0)?;
+            let from = transaction.from.ok_or_else(|| invalid_params("missing `from`"))?;
+            if let Some(nonce) = transaction.nonce {
+                check_nonce(evm.get_nonce(from).map_err(server_error)?, nonce)?;
+            }
+            let gas_limit = transaction.gas.map_or(evm.gas_limit, |gas| gas.low_u64());
+            let result = evm
+                .call_raw_committing(call_request(transaction)?, gas_limit).await
+                .map_err(server_error)?;
+            to_result(result.transaction_hash)
+        }
+        "eth_getTransactionByHash" => {
+            let hash: H256 = param(params, 0)?;
+            to_result(evm.get_transaction(hash).map(|transaction| &transaction.transaction))
+        }
+        "eth_getTransactionReceipt" => {
+            let hash: H256 = param(params, 0)?;
+            to_result(evm.get_transaction(hash).map(|transaction| &transaction.receipt))
+        }
+        "anvil_setBalance" | "hardhat_setBalance" => {
+            let address: Address = param(params, 0)?;
+            let balance: Uint = param(params, 1)?;
+            evm.override_account(address, Some(balance), None, None, None).map_err(override_error)?;
+            to_result(true)
+        }
+        "anvil_setCode" | "hardhat_setCode" => {
+            let address: Address = param(params, 0)?;
+            let code: Bytes = param(params, 1)?;
+            evm.override_account(address, None, None, Some(code), None).map_err(override_error)?;
+            to_result(true)
+        }
+        "anvil_setNonce" | "hardhat_setNonce" => {
+            let address: Address = param(params, 0)?;
+            let nonce = quantity_param(params, 1)?.ok_or_else(|| invalid_params("missing nonce"))?;
+            evm.override_account(address, None, Some(nonce), None, None).map_err(override_error)?;
+            to_result(true)
+        }
+        "anvil_setStorageAt" | "hardhat_setStorageAt" => {
+            let address: Address = param(params, 0)?;
+            let slot: Uint = param(params, 1)?;
+            let value: H256 = param(params, 2)?;
+            let mut key = [0u8; 32];
+            slot.to_big_endian(&mut key);
+            let storage = StorageOverride {
+                slots: [(H256::from(key), Uint::from_big_endian(value.as_bytes()))].into(),
+                diff: true,
+            };
+            evm.override_account(address, None, None, None, Some(storage)).map_err(override_error)?;
+            to_result(true)
+        }
+        // Transactions can be sent from any account, there is nothing to impersonate
+        "anvil_impersonateAccount" |
+        "anvil_stopImpersonatingAccount" |
+        "hardhat_impersonateAccount" |
+        "hardhat_stopImpersonatingAccount" => {
+            let _: Address = param(params, 0)?;
+            Ok(Value::Null)
+        }
+        "evm_mine" => {
+            let timestamp = quantity_param(params, 0)?;
+            let block_time = config.chains.block_time(evm.get_chain_id().as_u64());
+            // The timestamp applies to the block being mined, not to the one after it
+            if let Some(timestamp) = timestamp {
+                evm.set_block_timestamp(timestamp).await.map_err(server_error)?;
+            }
+            evm.mine(block_time);
+            to_result("0x0")
+        }
+        "evm_increaseTime" => {
+            let seconds = quantity_param(params, 0)?.ok_or_else(|| invalid_params("missing seconds"))?;
+            let time_offset = evm.increase_time(seconds).await.map_err(server_error)?;
+            to_result(U64::from(time_offset))
+        }
+        "evm_setNextBlockTimestamp" => {
+            let timestamp = quantity_param(params, 0)?.ok_or_else(|| invalid_params("missing timestamp"))?;
+            evm.set_block_timestamp(timestamp).await.map_err(server_error)?;
+            Ok(Value::Null)
+        }
+        "evm_snapshot" => to_result(evm.snapshot()),
+        "evm_revert" => {
+            let id: Uint = param(params, 0)?;
+            to_result(evm.revert(id, false))
+        }
+        _ =>
+            Err(JsonRpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("method {method} not found"),
+                data: None,
+            }),
+    }
+}
+
+/// Deserializes the positional parameter at `index`, missing parameters are read as `null`.
+fn param<T: DeserializeOwned>(params: &Value, index: usize) -> Result<T, JsonRpcError> {
+    let value = params.get(index).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value).map_err(|err| invalid_params(&err.to_string()))
+}
+
+/// Reads a quantity that clients send either as a JSON number or as a hex or decimal string.
+fn quantity_param(params: &Value, index: usize) -> Result<Option<u64>, JsonRpcError> {
+    match params.get(index) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(number)) => {
+            number.as_u64().map(Some).ok_or_else(|| invalid_params("expected an unsigned integer"))
+        }
+        Some(Value::String(value)) => {
+            let value: PermissiveUint = value.parse().map_err(|err: String| invalid_params(&err))?;
+            Ok(Some(Uint::from(value).low_u64()))
+        }
+        Some(_) => Err(invalid_params("expected a quantity")),
+    }
+}
+
+/// Sessions only have their current state, older blocks can't be queried.
+fn check_block(evm: &Evm, block: Option<BlockNumber>) -> Result<(), JsonRpcError> {
+    match block {
+        None | Some(BlockNumber::Latest) | Some(BlockNumber::Pending) => Ok(()),
+        Some(BlockNumber::Number(number)) if number.as_u64() == evm.get_block().as_u64() => Ok(()),
+        Some(block) =>
+            Err(JsonRpcError {
+                code: SERVER_ERROR,
+                message: format!("block {block} is not available, only the latest block can be queried"),
+                data: None,
+            }),
+    }
+}
+
+/// Transactions are not signed, but one sent with a nonce must use the next nonce of its sender.
+fn check_nonce(expected: u64, nonce: Uint) -> Result<(), JsonRpcError> {
+    let message = match nonce.cmp(&Uint::from(expected)) {
+        Ordering::Equal => {
+            return Ok(());
+        }
+        Ordering::Less => "nonce too low",
+        Ordering::Greater => "nonce too high",
+    };
+
+    Err(JsonRpcError {
+        code: SERVER_ERROR,
+        message: format!("{message}: next nonce {expected}, tx nonce {nonce}"),
+        data: None,
+    })
+}
+
+fn call_request(transaction: RpcTransactionRequest) -> Result<CallRawRequest, JsonRpcError> {
+    let to = transaction.to.ok_or_else(|| invalid_params("contract creation is not supported"))?;
+
+    Ok(CallRawRequest {
+        from: transaction.from.unwrap_or_default(),
+        to,
+        value: transaction.value,
+        data: transaction.data,
+        access_list: None,
+        format_trace: false,
+    })
+}
+
+fn check_reverted(result: &CallRawResult) -> Result<(), JsonRpcError> {
+    if result.success {
+        return Ok(());
+    }
+
+    Err(JsonRpcError {
+        code: EXECUTION_REVERTED,
+        message: "execution reverted".to_string(),
+        data: Some(Value::String(result.return_data.to_string())),
+    })
+}
+
+fn to_result<T: Serialize>(value: T) -> Result<Value, JsonRpcError> {
+    serde_json::to_value(value).map_err(|err| JsonRpcError {
+        code: SERVER_ERROR,
+        message: err.to_string(),
+        data: None,
+    })
+}
+
+fn invalid_params(message: &str) -> JsonRpcError {
+    JsonRpcError {
+        code: INVALID_PARAMS,
+        message: format!("invalid params: {message}"),
+        data: None,
+    }
+}
+
+fn override_error(_: OverrideError) -> JsonRpcError {
+    JsonRpcError {
+        code: SERVER_ERROR,
+        message: "failed to override account".to_string(),
+        data: None,
+    }
+}
+
+fn server_error(err: EvmError) -> JsonRpcError {
+    JsonRpcError {
+        code: SERVER_ERROR,
+        message: err.0.to_string(),
+        data: None,
+    }
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+use ethers::utils::{ hex, keccak256 };
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use warp::Rejection;
+
+use crate::structs::{ Config, Evm, SessionExpired, SessionInfo, SessionLimitReached, StateNotFound };
+use crate::SharedSimulationState;
+
+/// How long the ids of evicted sessions are remembered to answer with `SESSION_EXPIRED`.
+const EXPIRED_SESSION_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl SharedSimulationState {
+    /// Stores a new session, evicting the least recently used ones when a limit is reached. Fails if
+    /// the sessions that would have to be evicted are all busy.
+    pub fn insert_session(
+        &self,
+        mut evm: Evm,
+        api_key: Option<String>,
+        config: &Config
+    ) -> Result<Uuid, SessionLimitReached> {
+        let api_key_id = api_key.as_deref().map(api_key_id);
+        if let Some(max_sessions) = config.max_sessions_per_api_key {
+            self.evict_least_recently_used(max_sessions, |info| info.api_key_id == api_key_id)?;
+        }
+        if let Some(max_sessions) = config.max_sessions {
+            self.evict_least_recently_used(max_sessions, |_| true)?;
+        }
+
+        let id = Uuid::new_v4();
+        let now = Instant::now();
+        self.sessions.insert(id, SessionInfo {
+            api_key_id,
+            created_at: now,
+            last_used: now,
+            memory_size: evm.approximate_memory_size(),
+        });
+        self.persist_session(&id, &mut evm);
+        self.evms.insert(id, Arc::new(Mutex::new(evm)));
+
+        Ok(id)
+    }
+
+    /// Puts back a session under its previous id, without checking the limits.
+    pub fn restore_session(&self, id: Uuid, evm: Evm, info: SessionInfo) {
+        self.sessions.insert(id, info);
+        self.evms.insert(id, Arc::new(Mutex::new(evm)));
+    }
+
+    pub fn get_session(&self, id: &Uuid) -> Result<Arc<Mutex<Evm>>, Rejection> {
+        let evm = self.get_session_evm(id)?;
+        if let Some(mut info) = self.sessions.get_mut(id) {
+            info.last_used = Instant::now();
+        }
+
+        Ok(evm)
+    }
+
+    fn get_session_evm(&self, id: &Uuid) -> Result<Arc<Mutex<Evm>>, Rejection> {
+        let evm = self.evms.get(id).map(|evm| Arc::clone(evm.value()));
+
+        match evm {
+            Some(evm) => Ok(evm),
+            None if self.expired_sessions.contains_key(id) => {
+                Err(warp::reject::custom(SessionExpired))
+            }
+            None => Err(warp::reject::custom(StateNotFound())),
+        }
+    }
+
+    /// Looks up a session without counting it as used.
+    pub fn peek_session(&self, id: &Uuid) -> Result<(Arc<Mutex<Evm>>, SessionInfo), Rejection> {
+        let evm = self.get_session_evm(id)?;
+        let info = self.sessions
+            .get(id)
+            .map(|info| info.value().clone())
+            .ok_or_else(|| warp::reject::custom(StateNotFound()))?;
+
+        Ok((evm, info))
+    }
+
+    pub fn remove_session(&self, id: &Uuid) -> bool {
+        self.sessions.remove(id);
+        if let Some(store) = &self.store {
+            store.remove(id);
+        }
+        self.evms.remove(id).is_some()
+    }
+
+    /// Expires sessions past their TTLs and evicts the least recently used ones while the
+    /// sessions use more memory than allowed.
+    pub fn reap_sessions(&self, config: &Config) {
+        let now = Instant::now();
+        let exceeds = |since: Instant, ttl: Option<u64>| {
+            ttl.map_or(false, |ttl| now.duration_since(since) > Duration::from_secs(ttl))
+        };
+
+        let expired: Vec<Uuid> = self.sessions
+            .iter()
+            .filter(|entry| {
+                exceeds(entry.last_used, config.session_idle_ttl) ||
+                    exceeds(entry.created_at, config.session_max_ttl)
+            })
+            .map(|entry| *entry.key())
+            .collect();
+        for id in expired {
+            self.expire_session(&id);
+        }
+
+        if let Some(memory_limit) = config.session_memory_limit {
+            // Sessions that are busy count with the size they last had, but they are in use, so
+            // they are not the least recently used anyway
+            let mut sessions: Vec<(Uuid, Instant, usize, bool)> = self.sessions
+                .iter_mut()
+                .map(|mut entry| {
+                    let evm = self.evms.get(entry.key()).map(|evm| Arc::clone(evm.value()));
+                    let idle = match evm.as_ref().and_then(|evm| evm.try_lock().ok()) {
+                        Some(evm) => {
+                            entry.memory_size = evm.approximate_memory_size();
+                            true
+                        }
+                        None => false,
+                    };
+                    (*entry.key(), entry.last_used, entry.memory_size, idle)
+                })
+                .collect();
+            sessions.sort_by_key(|(_, last_used, _, _)| *last_used);
+
+            let mut total: usize = sessions.iter().map(|(_, _, memory_size, _)| memory_size).sum();
+            for (id, _, memory_size, idle) in sessions {
+                if total <= memory_limit {
+                    break;
+                }
+                if idle {
+                    self.expire_session(&id);
+                    total -= memory_size;
+                }
+            }
+        }
+
+        if let Some(store) = &self.store {
+            store.prune(&self.sessions);
+        }
+
+        self.expired_sessions.retain(|_, expired_at| {
+            now.duration_since(*expired_at) < EXPIRED_SESSION_RETENTION
+        });
+    }
+
+    fn expire_session(&self, id: &Uuid) {
+        if self.remove_session(id) {
+            log::info!(target: "ts::api", "Expired stateful simulation {id}");
+            self.expired_sessions.insert(*id, Instant::now());
+        }
+    }
+
+    /// Evicts the least recently used sessions matching `filter` until a new one fits in `max_sessions`.
+    /// Sessions that are busy running a request are not evicted, if there are not enough idle ones
+    /// none is.
+    fn evict_least_recently_used(
+        &self,
+        max_sessions: usize,
+        filter: impl Fn(&SessionInfo) -> bool
+    ) -> Result<(), SessionLimitReached> {
+        let mut sessions: Vec<(Uuid, Instant)> = self.sessions
+            .iter()
+            .filter(|entry| filter(entry.value()))
+            .map(|entry| (*entry.key(), entry.last_used))
+            .collect();
+        if sessions.len() < max_sessions {
+            return Ok(());
+        }
+
+        sessions.sort_by_key(|(_, last_used)| *last_used);
+        let excess = sessions.len() + 1 - max_sessions;
+        let idle: Vec<Uuid> = sessions
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| self.is_idle(id))
+            .take(excess)
+            .collect();
+        if idle.len() < excess {
+            return Err(SessionLimitReached);
+        }
+        for id in idle {
+            self.expire_session(&id);
+        }
+        Ok(())
+    }
+
+    fn is_idle(&self, id: &Uuid) -> bool {
+        let evm = self.evms.get(id).map(|evm| Arc::clone(evm.value()));
+        evm.map_or(true, |evm| evm.try_lock().is_ok())
+    }
+}
+
+/// Identifies an API key without revealing it.
+fn api_key_id(api_key: &str) -> String {
+    hex::encode(keccak256(api_key.as_bytes()))
+}
+
+/// Converts an `Instant` in the past to seconds since the Unix epoch.
+pub fn to_unix_timestamp(instant: Instant) -> u64 {
+    SystemTime::now()
+        .checked_sub(instant.elapsed())
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Periodically expires and evicts sessions according to the configured limits.
+pub async fn reap_sessions(state: Arc<SharedSimulationState>, config: Config) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.session_reaper_interval));
+
+    loop {
+        interval.tick().await;
+        state.reap_sessions(&config);
+    }
+}
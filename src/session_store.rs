@@ -0,0 +1,280 @@
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::Ordering;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+use dashmap::DashMap;
+use uuid::Uuid;
+
+use crate::session::to_unix_timestamp;
+use crate::simulation::new_stateful_evm;
+use crate::structs::{
+    Config,
+    Evm,
+    PersistedFork,
+    PersistedSession,
+    SerializableState,
+    SessionInfo,
+    SessionStore,
+    StatefulSimulationForkRequest,
+    StatefulSimulationRequest,
+};
+use crate::SharedSimulationState;
+
+impl SessionStore {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let dir = PathBuf::from(config.session_store_dir.as_ref()?);
+        Some(SessionStore {
+            dir,
+            retention: config.session_store_retention.map(Duration::from_secs),
+            written_versions: Default::default(),
+            next_version: Default::default(),
+        })
+    }
+
+    /// Saves the fork parameters and local state of a session, on all of its chains, to
+    /// `{dir}/{id}.json`. The state is taken right away, but it is serialized and written on a
+    /// blocking thread, in the background.
+    pub fn save(&self, id: &Uuid, info: &SessionInfo, evm: &mut Evm) {
+        let forks = match evm.dump_forks() {
+            Ok(forks) => forks,
+            Err(err) => {
+                log::warn!(target: "ts::api", "Failed to store stateful simulation {id}: {err:?}");
+                return;
+            }
+        };
+        let session = PersistedSession {
+            id: *id,
+            api_key_id: info.api_key_id.clone(),
+            created_at: to_unix_timestamp(info.created_at),
+            last_used: to_unix_timestamp(info.last_used),
+            simulation: StatefulSimulationRequest {
+                chain_id: evm.get_chain_id().as_u64(),
+                gas_limit: evm.gas_limit,
+                block_number: Some(evm.fork_block_number),
+                block_timestamp: None,
+                forks: (!forks.is_empty()).then(|| {
+                    forks
+                        .iter()
+                        .map(|fork| StatefulSimulationForkRequest {
+                            chain_id: fork.chain_id,
+                            block_number: Some(fork.block_number),
+                        })
+                        .collect()
+                }),
+            },
+            state: evm.dump_state(),
+            forks,
+        };
+        let version = self.next_version.fetch_add(1, Ordering::Relaxed) + 1;
+        self.written_versions.entry(*id).or_default().pending += 1;
+
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let id = session.id;
+            if let Err(err) = store.write(session, version) {
+                log::warn!(target: "ts::api", "Failed to store stateful simulation {id}: {err}");
+            }
+        });
+    }
+
+    /// Writes a session unless a newer version of it was written already, or it was removed.
+    fn write(&self, session: PersistedSession, version: u64) -> io::Result<()> {
+        let id = session.id;
+        let result = self.write_version(session, version);
+        // The last save in flight of a removed session is the last one to need its versions
+        self.written_versions.remove_if(&id, |_, versions| versions.written == u64::MAX && versions.pending == 0);
+        result
+    }
+
+    fn write_version(&self, session: PersistedSession, version: u64) -> io::Result<()> {
+        // The entry stays locked until the file is written, so versions are written in order
+        let mut versions = self.written_versions.entry(session.id).or_default();
+        versions.pending = versions.pending.saturating_sub(1);
+        if versions.written > version {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first so a crash never leaves a truncated session behind
+        let tmp_path = self.dir.join(format!("{}.json.tmp", session.id));
+        fs::write(&tmp_path, serde_json::to_vec(&session)?)?;
+        fs::rename(tmp_path, self.path(&session.id))?;
+        versions.written = version;
+        Ok(())
+    }
+
+    /// Deletes a removed session, dropping its saves still in flight rather than letting them
+    /// bring the file back.
+    pub fn remove(&self, id: &Uuid) {
+        self.written_versions.entry(*id).or_default().written = u64::MAX;
+        self.remove_file(id);
+        self.written_versions.remove_if(id, |_, versions| versions.pending == 0);
+    }
+
+    fn remove_file(&self, id: &Uuid) {
+        match fs::remove_file(self.path(id)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => log::warn!(target: "ts::api", "Failed to remove stored session {id}: {err}"),
+        }
+    }
+
+    /// Reads all stored sessions, deleting the ones not used within the retention period.
+    pub fn load_all(&self) -> Vec<PersistedSession> {
+        self.stored_files()
+            .into_iter()
+            .filter_map(|(_, path)| {
+                let session = read_session(&path);
+                match session {
+                    Ok(session) if self.is_past_retention(session.last_used) => {
+                        self.remove_file(&session.id);
+                        None
+                    }
+                    Ok(session) => Some(session),
+                    Err(err) => {
+                        log::warn!(target: "ts::api", "Skipping stored session {}: {err}", path.display());
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Deletes the stored sessions not used within the retention period. The sessions in memory
+    /// are judged by `sessions`, the others by the last use they were saved with. Forgets the
+    /// versions of the sessions that are gone.
+    pub fn prune(&self, sessions: &DashMap<Uuid, SessionInfo>) {
+        // Only the sessions in memory and the saves in flight need their versions
+        self.written_versions.retain(|id, versions| versions.pending > 0 || sessions.contains_key(id));
+
+        if self.retention.is_none() {
+            return;
+        }
+
+        for (id, path) in self.stored_files() {
+            let last_used = match sessions.get(&id) {
+                Some(info) => to_unix_timestamp(info.last_used),
+                None => match read_session(&path) {
+                    Ok(session) => session.last_used,
+                    Err(_) => continue,
+                },
+            };
+            // A session still in memory is saved again the next time it changes
+            if self.is_past_retention(last_used) {
+                self.remove_file(&id);
+            }
+        }
+    }
+
+    fn is_past_retention(&self, last_used: u64) -> bool {
+        self.retention.map_or(false, |retention| {
+            unix_now().saturating_sub(last_used) > retention.as_secs()
+        })
+    }
+
+    /// Lists the files of the stored sessions with their ids.
+    fn stored_files(&self) -> Vec<(Uuid, PathBuf)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return vec![],
+            Err(err) => {
+                log::warn!(target: "ts::api", "Failed to read session store: {err}");
+                return vec![];
+            }
+        };
+
+        entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |extension| extension == "json"))
+            .filter_map(|path| {
+                let id = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((id, path))
+            })
+            .collect()
+    }
+
+    fn path(&self, id: &Uuid) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+}
+
+fn read_session(path: &Path) -> Result<PersistedSession, String> {
+    let bytes = fs::read(path).map_err(|err| err.to_string())?;
+    serde_json::from_slice(&bytes).map_err(|err| err.to_string())
+}
+
+impl SharedSimulationState {
+    pub fn new(config: &Config) -> Self {
+        SharedSimulationState {
+            store: SessionStore::from_config(config),
+            ..Default::default()
+        }
+    }
+
+    /// Saves a session to the session store, if there is one, after it changed. Its memory size is
+    /// updated as well, for the reaper to account for it while it is busy.
+    pub fn persist_session(&self, id: &Uuid, evm: &mut Evm) {
+        let info = self.sessions.get_mut(id).map(|mut info| {
+            info.memory_size = evm.approximate_memory_size();
+            info.value().clone()
+        });
+        if let (Some(store), Some(info)) = (&self.store, info) {
+            store.save(id, &info, evm);
+        }
+    }
+
+    /// Recreates the sessions of the session store by forking again and applying their state.
+    pub async fn restore_sessions(&self, config: &Config) {
+        let Some(store) = &self.store else {
+            return;
+        };
+
+        for session in store.load_all() {
+            let id = session.id;
+            let evm = match new_stateful_evm(&session.simulation, config).await {
+                Ok(evm) => load_session_state(evm, session.state, session.forks),
+                Err(_) => None,
+            };
+            let Some(evm) = evm else {
+                log::warn!(target: "ts::api", "Failed to restore stateful simulation {id}");
+                continue;
+            };
+
+            let memory_size = evm.approximate_memory_size();
+            self.restore_session(id, evm, SessionInfo {
+                api_key_id: session.api_key_id,
+                created_at: from_unix_timestamp(session.created_at),
+                last_used: from_unix_timestamp(session.last_used),
+                memory_size,
+            });
+            log::info!(target: "ts::api", "Restored stateful simulation {id}");
+        }
+    }
+}
+
+/// Applies the state of a stored session to the chain it was created on and to its other forks.
+fn load_session_state(mut evm: Evm, state: SerializableState, forks: Vec<PersistedFork>) -> Option<Evm> {
+    let chain_id = evm.get_chain_id().as_u64();
+    evm.load_state(state).ok()?;
+    for fork in forks {
+        evm.select_chain(fork.chain_id).ok()?;
+        evm.load_state(fork.state).ok()?;
+    }
+    evm.select_chain(chain_id).ok()?;
+    Some(evm)
+}
+
+/// Converts seconds since the Unix epoch to an `Instant`, which is now at the latest.
+fn from_unix_timestamp(timestamp: u64) -> Instant {
+    let age = Duration::from_secs(unix_now().saturating_sub(timestamp));
+    let now = Instant::now();
+    now.checked_sub(age).unwrap_or(now)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
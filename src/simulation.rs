@@ -1,32 +1,74 @@
+use std::collections::HashMap;
+use std::future::{ self, Future };
 use std::str::FromStr;
 use std::sync::Arc;
 use crate::structs::StorageOverride;
 use crate::SharedSimulationState;
-use dashmap::mapref::one::RefMut;
-use ethers::abi::Uint;
+use crate::session::to_unix_timestamp;
+use ethers::abi::{ Address, Uint };
+use ethers::types::{ BlockNumber, Filter, Transaction, H256 };
+use ethers::utils::{ keccak256, rlp };
 use serde::Deserialize;
-use tokio::sync::Mutex;
+use tokio::sync::OwnedMutexGuard;
 use uuid::Uuid;
-use warp::reply::Json;
+use warp::reply::{ Json, WithHeader };
 use warp::Rejection;
 
 use crate::structs::{
         SimulationRequest,
+        SimulationBundleRequest,
+        RevertPolicy,
+        CallBundleRequest,
+        CallBundleResponse,
+        CallBundleTransactionResult,
         SimulationResponse,
+        MultiChainSimulationResponse,
+        ChainSimulationResponse,
         StatefulSimulationRequest,
+        StatefulSimulationLoadRequest,
         StatefulSimulationResponse,
         StatefulSimulationEndResponse,
+        StatefulSimulationInfoResponse,
+        SessionInfo,
+        SessionTransaction,
+        StatefulSimulationSnapshotResponse,
+        StatefulSimulationRevertRequest,
+        StatefulSimulationRevertResponse,
+        StatefulSimulationStateRequest,
+        StatefulSimulationStateResponse,
+        StatefulSimulationAccountResponse,
+        StatefulSimulationRollForkRequest,
+        StatefulSimulationRollForkResponse,
+        StatefulSimulationStorageResponse,
+        StateOverride,
         CallTrace,
         PermissiveUint,
         State,
+        EvmError,
+        FailedInstantiateFork,
         IncorrectChainIdError,
         InvalidBlockNumbersError,
-        MultipleChainIdsError,
         NoURLForChainIdError,
+        MultipleChainIdsError,
         StateNotFound,
+        SnapshotNotFound,
         FailedToSetBlockTimestamp,
+        InvalidRawTransactionError,
+        ContractCreationNotSupported,
+        HistoricalStateUnavailable,
+        UpstreamChainMismatch,
+        BackendKey,
+        CassetteMode,
+        ForkError,
+        ForkSource,
+        UpstreamFailure,
+        UpstreamLease,
+        UpstreamPolicy,
+        UpstreamUnreachable,
+        WarmFork,
     };
 
+use super::evm::{ fork_url_alias, OFFLINE_FORK_URL };
 use super::structs::Config;
 use super::structs::{ CallRawRequest, Evm };
 
@@ -53,55 +95,62 @@ impl From<PermissiveUint> for Uint {
     }
 }
 
-impl<'de> Deserialize<'de> for PermissiveUint {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+impl FromStr for PermissiveUint {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
         // Accept value in hex or decimal formats
-        let value = String::deserialize(deserializer)?;
         let parsed = if value.starts_with("0x") {
-            Uint::from_str(&value).map_err(serde::de::Error::custom)?
+            Uint::from_str(value).map_err(|err| err.to_string())?
         } else {
-            Uint::from_dec_str(&value).map_err(serde::de::Error::custom)?
+            Uint::from_dec_str(value).map_err(|err| err.to_string())?
         };
         Ok(Self(parsed))
     }
 }
 
-fn chain_id_to_fork_url(chain_id: u64) -> Result<String, Rejection> {
-    match chain_id {
-        // ethereum
-        1 => Ok("https://eth.llamarpc.com".to_string()),
-        5 => Ok("https://eth-goerli.g.alchemy.com/v2/demo".to_string()),
-        11155111 => Ok("https://eth-sepolia.g.alchemy.com/v2/demo".to_string()),
-        // polygon
-        137 => Ok("https://polygon-mainnet.g.alchemy.com/v2/demo".to_string()),
-        80001 => Ok("https://polygon-mumbai.g.alchemy.com/v2/demo".to_string()),
-        // avalanche
-        43114 => Ok("https://api.avax.network/ext/bc/C/rpc".to_string()),
-        43113 => Ok("https://api.avax-test.network/ext/bc/C/rpc".to_string()),
-        // fantom
-        250 => Ok("https://rpcapi.fantom.network/".to_string()),
-        4002 => Ok("https://rpc.testnet.fantom.network/".to_string()),
-        // xdai
-        100 => Ok("https://rpc.xdaichain.com/".to_string()),
-        // bsc
-        56 => Ok("https://bsc-dataseed.binance.org/".to_string()),
-        97 => Ok("https://data-seed-prebsc-1-s1.binance.org:8545/".to_string()),
-        // arbitrum
-        42161 => Ok("https://arb1.arbitrum.io/rpc".to_string()),
-        421613 => Ok("https://goerli-rollup.arbitrum.io/rpc".to_string()),
-        // optimism
-        10 => Ok("https://mainnet.optimism.io/".to_string()),
-        420 => Ok("https://goerli.optimism.io/".to_string()),
-        _ => Err(NoURLForChainIdError.into()),
+impl<'de> Deserialize<'de> for PermissiveUint {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
     }
 }
 
-async fn run(
+impl<'de> Deserialize<'de> for SimulationBundleRequest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+        // Accept a bare array of transactions or an object carrying bundle options
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct BundleOptions {
+            transactions: Vec<SimulationRequest>,
+            #[serde(default)]
+            revert_policy: RevertPolicy,
+        }
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let bundle = if value.is_array() {
+            BundleOptions {
+                transactions: serde_json::from_value(value).map_err(serde::de::Error::custom)?,
+                revert_policy: RevertPolicy::default(),
+            }
+        } else {
+            serde_json::from_value(value).map_err(serde::de::Error::custom)?
+        };
+        if bundle.transactions.is_empty() {
+            return Err(serde::de::Error::custom("a bundle needs at least one transaction"));
+        }
+        Ok(Self {
+            transactions: bundle.transactions,
+            revert_policy: bundle.revert_policy,
+        })
+    }
+}
+
+fn apply_state_overrides(
     evm: &mut Evm,
-    transaction: SimulationRequest,
-    commit: bool
-) -> Result<SimulationResponse, Rejection> {
-    for (address, state_override) in transaction.state_overrides.into_iter().flatten() {
+    state_overrides: Option<HashMap<Address, StateOverride>>
+) -> Result<(), Rejection> {
+    for (address, state_override) in state_overrides.into_iter().flatten() {
         evm.override_account(
             address,
             state_override.balance.map(Uint::from),
@@ -111,6 +160,16 @@ async fn run(
         )?;
     }
 
+    Ok(())
+}
+
+async fn run(
+    evm: &mut Evm,
+    transaction: SimulationRequest,
+    commit: bool
+) -> Result<SimulationResponse, Rejection> {
+    apply_state_overrides(evm, transaction.state_overrides)?;
+
     let call = CallRawRequest {
         from: transaction.from,
         to: transaction.to,
@@ -120,10 +179,11 @@ async fn run(
         format_trace: transaction.format_trace.unwrap_or_default(),
     };
     let result = if commit {
-        evm.call_raw_committing(call, transaction.gas_limit).await?
+        evm.call_raw_committing(call, transaction.gas_limit).await
     } else {
-        evm.call_raw(call).await?
+        evm.call_raw(call).await
     };
+    let result = result.map_err(|err| upstream_error(evm, err))?;
 
     Ok(SimulationResponse {
         simulation_id: 1,
@@ -135,26 +195,38 @@ async fn run(
         exit_reason: result.exit_reason,
         formatted_trace: result.formatted_trace,
         return_data: result.return_data,
+        transaction_hash: result.transaction_hash,
     })
 }
 
-pub async fn simulate(transaction: SimulationRequest, config: Config) -> Result<Json, Rejection> {
-    let fork_url = config
-        .fork_url
-        .map_or_else(|| chain_id_to_fork_url(transaction.chain_id), Ok)?;
+pub async fn simulate(
+    transaction: SimulationRequest,
+    config: Config
+) -> Result<WithHeader<Json>, Rejection> {
+    let (response, upstream) = with_failover(&config, transaction.chain_id, transaction.block_number, |source| {
+        simulate_on(source, transaction.clone(), &config)
+    }).await?;
+
+    Ok(with_upstream(warp::reply::json(&response), &upstream))
+}
 
+async fn simulate_on(
+    source: ForkSource,
+    transaction: SimulationRequest,
+    config: &Config
+) -> Result<SimulationResponse, Rejection> {
     let mut evm = Evm::new(
         None,
-        fork_url,
+        source,
         transaction.block_number,
         transaction.gas_limit,
         true,
-        config.etherscan_key,
+        config.etherscan_key.clone(),
     )
-    .map_err(|err| warp::reject::custom(err))?;
+    .map_err(Rejection::from)?;
 
     if evm.get_chain_id() != Uint::from(transaction.chain_id) {
-        return Err(warp::reject::custom(IncorrectChainIdError()));
+        return Err(warp::reject::custom(UpstreamChainMismatch));
     }
 
     if let Some(timestamp) = transaction.block_timestamp {
@@ -163,98 +235,532 @@ pub async fn simulate(transaction: SimulationRequest, config: Config) -> Result<
             .map_err(|_| warp::reject::custom(FailedToSetBlockTimestamp))?;
     }
 
-    let response = run(&mut evm, transaction, false).await?;
-
-    Ok(warp::reply::json(&response))
+    run(&mut evm, transaction, false).await
 }
 
 pub async fn simulate_bundle(
-    transactions: Vec<SimulationRequest>,
+    bundle: SimulationBundleRequest,
     config: Config
-) -> Result<Json, Rejection> {
+) -> Result<WithHeader<Json>, Rejection> {
+    let first_chain_id = bundle.transactions[0].chain_id;
+    let first_block_number = bundle.transactions[0].block_number;
+
+    let (response, upstream) = with_failover(&config, first_chain_id, first_block_number, |source| {
+        simulate_bundle_on(source, bundle.clone(), &config)
+    }).await?;
+
+    Ok(with_upstream(warp::reply::json(&response), &upstream))
+}
+
+async fn simulate_bundle_on(
+    source: ForkSource,
+    bundle: SimulationBundleRequest,
+    config: &Config
+) -> Result<Vec<SimulationResponse>, Rejection> {
+    let SimulationBundleRequest { transactions, revert_policy } = bundle;
     let first_chain_id = transactions[0].chain_id;
     let first_block_number = transactions[0].block_number;
     let first_block_timestamp = transactions[0].block_timestamp;
 
-    let fork_url = config.fork_url.unwrap_or(chain_id_to_fork_url(first_chain_id)?);
-
     // Obtain the EVM from the Result<Evm, CustomRejection>.
     let mut evm = match
         Evm::new(
             None,
-            fork_url,
+            source,
             first_block_number,
             transactions[0].gas_limit,
             true,
-            config.etherscan_key
+            config.etherscan_key.clone()
         )
     {
         Ok(evm) => evm, // Successfully obtained the EVM.
         Err(err) => {
-            return Err(warp::reject::custom(err));
+            return Err(err.into());
         } // Return the rejection error.
     };
 
     if evm.get_chain_id() != Uint::from(first_chain_id) {
-        return Err(warp::reject::custom(IncorrectChainIdError()));
+        return Err(warp::reject::custom(UpstreamChainMismatch));
     }
 
     if let Some(timestamp) = first_block_timestamp {
-        evm.set_block_timestamp(timestamp).await.expect("failed to set block timestamp");
+        evm.set_block_timestamp(timestamp)
+            .await
+            .map_err(|_| warp::reject::custom(FailedToSetBlockTimestamp))?;
     }
 
     let response = Vec::with_capacity(transactions.len());
-    let response = process_transactions(&mut evm, transactions, response).await?;
+    process_transactions(&mut evm, config, transactions, revert_policy, response).await
+}
 
-    Ok(warp::reply::json(&response))
+pub async fn call_bundle(
+    request: CallBundleRequest,
+    config: Config
+) -> Result<WithHeader<Json>, Rejection> {
+    let transactions = request.txs
+        .iter()
+        .map(|raw| {
+            let mut transaction: Transaction = rlp::decode(raw).map_err(|_| InvalidRawTransactionError)?;
+            transaction.recover_from_mut().map_err(|_| InvalidRawTransactionError)?;
+            Ok(transaction)
+        })
+        .collect::<Result<Vec<Transaction>, InvalidRawTransactionError>>()?;
+
+    if transactions.is_empty() {
+        return Err(warp::reject::custom(InvalidRawTransactionError));
+    }
+    // The executor only runs calls, deployments would need the init code to be run instead
+    if transactions.iter().any(|transaction| transaction.to.is_none()) {
+        return Err(warp::reject::custom(ContractCreationNotSupported));
+    }
+
+    let chain_id = request.chain_id
+        .or_else(|| transactions[0].chain_id.map(|chain_id| chain_id.as_u64()))
+        .unwrap_or(1);
+
+    let state_block_number = match request.state_block_number {
+        BlockNumber::Number(number) => Some(number.as_u64()),
+        _ => None,
+    };
+
+    let (response, upstream) = with_failover(&config, chain_id, state_block_number, |source| {
+        call_bundle_on(source, &request, &transactions, chain_id, state_block_number, &config)
+    }).await?;
+
+    Ok(with_upstream(warp::reply::json(&response), &upstream))
+}
+
+async fn call_bundle_on(
+    source: ForkSource,
+    request: &CallBundleRequest,
+    transactions: &[Transaction],
+    chain_id: u64,
+    state_block_number: Option<u64>,
+    config: &Config
+) -> Result<CallBundleResponse, Rejection> {
+    let gas_limit = transactions
+        .iter()
+        .map(|transaction| transaction.gas.low_u64())
+        .max()
+        .unwrap_or_default();
+
+    let mut evm = Evm::new(None, source, state_block_number, gas_limit, false, config.etherscan_key.clone())
+        .map_err(Rejection::from)?;
+
+    if evm.get_chain_id() != Uint::from(chain_id) {
+        return Err(warp::reject::custom(UpstreamChainMismatch));
+    }
+
+    let state_block_number = evm.get_block().as_u64();
+    let block_number = request.block_number.as_u64();
+    if block_number < state_block_number {
+        return Err(warp::reject::custom(InvalidBlockNumbersError()));
+    }
+    let timestamp = request.timestamp.unwrap_or(
+        evm.get_block_timestamp().as_u64() +
+            config.chains.block_time(chain_id) * (block_number - state_block_number)
+    );
+    evm.set_block(block_number).await?;
+    evm.set_block_timestamp(timestamp).await?;
+
+    let coinbase = evm.get_coinbase();
+    let basefee = evm.get_basefee();
+    let mut results = Vec::with_capacity(transactions.len());
+    let mut total_coinbase_diff = Uint::zero();
+    let mut total_eth_sent_to_coinbase = Uint::zero();
+    let mut total_gas_fees = Uint::zero();
+
+    for transaction in transactions {
+        let to = transaction.to.ok_or(ContractCreationNotSupported)?;
+        let balance_before = evm.get_balance(coinbase).map_err(|err| upstream_error(&evm, err))?;
+
+        let call = CallRawRequest {
+            from: transaction.from,
+            to,
+            value: Some(transaction.value),
+            data: Some(transaction.input.clone()),
+            access_list: transaction.access_list.clone(),
+            format_trace: false,
+        };
+        let result = evm
+            .call_raw_committing(call, transaction.gas.low_u64()).await
+            .map_err(|err| upstream_error(&evm, err))?;
+
+        // The executor does not charge gas, so fees are derived from the gas used and only
+        // direct transfers show up in the coinbase balance.
+        let eth_sent_to_coinbase = evm
+            .get_balance(coinbase)
+            .map_err(|err| upstream_error(&evm, err))?
+            .saturating_sub(balance_before);
+        let gas_fees = Uint::from(result.gas_used) * effective_priority_fee(transaction, basefee);
+        let coinbase_diff = eth_sent_to_coinbase + gas_fees;
+        total_coinbase_diff += coinbase_diff;
+        total_eth_sent_to_coinbase += eth_sent_to_coinbase;
+        total_gas_fees += gas_fees;
+
+        results.push(CallBundleTransactionResult {
+            coinbase_diff: coinbase_diff.to_string(),
+            eth_sent_to_coinbase: eth_sent_to_coinbase.to_string(),
+            from_address: transaction.from,
+            gas_fees: gas_fees.to_string(),
+            gas_price: (coinbase_diff / result.gas_used.max(1)).to_string(),
+            gas_used: result.gas_used,
+            to_address: to,
+            tx_hash: transaction.hash,
+            value: result.success.then(|| result.return_data.clone()),
+            error: (!result.success).then(|| format!("{:?}", result.exit_reason)),
+            revert: (!result.success).then_some(result.return_data),
+        });
+    }
+
+    let total_gas_used: u64 = results.iter().map(|result| result.gas_used).sum();
+    let bundle_hash = H256(
+        keccak256(results.iter().flat_map(|result| result.tx_hash.0).collect::<Vec<u8>>())
+    );
+
+    let response = CallBundleResponse {
+        bundle_gas_price: (total_coinbase_diff / total_gas_used.max(1)).to_string(),
+        bundle_hash,
+        coinbase_diff: total_coinbase_diff.to_string(),
+        eth_sent_to_coinbase: total_eth_sent_to_coinbase.to_string(),
+        gas_fees: total_gas_fees.to_string(),
+        results,
+        state_block_number,
+        total_gas_used,
+    };
+
+    Ok(response)
+}
+
+fn effective_priority_fee(transaction: &Transaction, basefee: Uint) -> Uint {
+    match (transaction.max_fee_per_gas, transaction.max_priority_fee_per_gas) {
+        (Some(max_fee), Some(max_priority_fee)) =>
+            max_priority_fee.min(max_fee.saturating_sub(basefee)),
+        _ => transaction.gas_price.unwrap_or_default().saturating_sub(basefee),
+    }
 }
 
 pub async fn simulate_stateful_new(
     stateful_simulation_request: StatefulSimulationRequest,
+    api_key: Option<String>,
+    config: Config,
+    state: Arc<SharedSimulationState>
+) -> Result<WithHeader<Json>, Rejection> {
+    let evm = new_stateful_evm(&stateful_simulation_request, &config).await?;
+    let upstream = evm.fork_url.clone();
+
+    let new_id = state.insert_session(evm, api_key, &config)?;
+
+    let response = StatefulSimulationResponse {
+        stateful_simulation_id: new_id,
+    };
+
+    Ok(with_upstream(warp::reply::json(&response), &upstream))
+}
+
+pub async fn simulate_stateful_load(
+    load_request: StatefulSimulationLoadRequest,
+    api_key: Option<String>,
+    config: Config,
+    state: Arc<SharedSimulationState>
+) -> Result<WithHeader<Json>, Rejection> {
+    let mut evm = new_stateful_evm(&load_request.simulation, &config).await?;
+    evm.load_state(load_request.state)?;
+    let upstream = evm.fork_url.clone();
+
+    let new_id = state.insert_session(evm, api_key, &config)?;
+
+    let response = StatefulSimulationResponse {
+        stateful_simulation_id: new_id,
+    };
+
+    Ok(with_upstream(warp::reply::json(&response), &upstream))
+}
+
+pub async fn simulate_stateful_clone(
+    evm: OwnedMutexGuard<Evm>,
+    api_key: Option<String>,
     config: Config,
     state: Arc<SharedSimulationState>
 ) -> Result<Json, Rejection> {
-    let fork_url = config.fork_url.unwrap_or(
-        chain_id_to_fork_url(stateful_simulation_request.chain_id)?
-    );
+    let clone = evm.duplicate(config.etherscan_key.clone());
+    drop(evm);
 
-    // Obtain the EVM from the Result<Evm, CustomRejection>.
-    let mut evm = match
-        Evm::new(
-            None,
-            fork_url,
-            stateful_simulation_request.block_number,
-            stateful_simulation_request.gas_limit,
-            true,
-            config.etherscan_key
-        )
-    {
-        Ok(evm) => evm, // Successfully obtained the EVM.
-        Err(err) => {
-            return Err(warp::reject::custom(err));
-        } // Return the rejection error.
+    let new_id = state.insert_session(clone, api_key, &config)?;
+
+    let response = StatefulSimulationResponse {
+        stateful_simulation_id: new_id,
     };
 
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn simulate_stateful_dump(evm: OwnedMutexGuard<Evm>) -> Result<Json, Rejection> {
+    let response = evm.dump_state();
+
+    Ok(warp::reply::json(&response))
+}
+
+pub(crate) async fn new_stateful_evm(
+    stateful_simulation_request: &StatefulSimulationRequest,
+    config: &Config
+) -> Result<Evm, Rejection> {
+    let chain_id = stateful_simulation_request.chain_id;
+    let block_number = stateful_simulation_request.block_number;
+    let (mut evm, _) = with_failover(config, chain_id, block_number, |source| {
+        future::ready(
+            Evm::new(
+                None,
+                source,
+                stateful_simulation_request.block_number,
+                stateful_simulation_request.gas_limit,
+                true,
+                config.etherscan_key.clone()
+            )
+                .map_err(Rejection::from)
+                .and_then(|evm| {
+                    if evm.get_chain_id() != Uint::from(stateful_simulation_request.chain_id) {
+                        return Err(warp::reject::custom(UpstreamChainMismatch));
+                    }
+                    Ok(evm)
+                })
+        )
+    }).await?;
+
+    evm.record_transactions = true;
     if let Some(timestamp) = stateful_simulation_request.block_timestamp {
         evm.set_block_timestamp(timestamp).await?;
     }
 
-    let new_id = Uuid::new_v4();
-    state.evms.insert(new_id, Arc::new(Mutex::new(evm)));
+    for fork in stateful_simulation_request.forks.iter().flatten() {
+        add_chain_fork(&mut evm, config, fork.chain_id, fork.block_number)?;
+    }
 
-    let response = StatefulSimulationResponse {
-        stateful_simulation_id: new_id,
+    Ok(evm)
+}
+
+/// Adds a fork of another chain to a stateful simulation, at the latest block unless
+/// `block_number` is given.
+fn add_chain_fork(
+    evm: &mut Evm,
+    config: &Config,
+    chain_id: u64,
+    block_number: Option<u64>
+) -> Result<(), Rejection> {
+    // Offline simulations only have the chain of the offline state
+    if config.offline_state.is_some() {
+        return Err(warp::reject::custom(NoURLForChainIdError));
+    }
+
+    let mut result = Err(NoURLForChainIdError.into());
+    let mut failed = Vec::new();
+    for fork_url in config.chains.upstreams(chain_id)? {
+        let upstream = match UpstreamLease::new(&fork_url, chain_id, UpstreamPolicy::from_config(config)) {
+            Ok(upstream) => upstream,
+            Err(err) => {
+                failed.push(fork_url);
+                result = Err(err.into());
+                continue;
+            }
+        };
+
+        let err = match evm.add_fork(Arc::clone(&upstream), chain_id, block_number) {
+            Ok(()) => {
+                mark_failed(config, &failed);
+                config.chains.mark_healthy(&fork_url);
+                return Ok(());
+            }
+            Err(err) => failover_error(err.into(), &upstream)?,
+        };
+        failed.push(fork_url);
+        result = Err(err);
+    }
+    result
+}
+
+/// Runs a simulation on the upstreams of a chain in turn until one of them serves it, skipping
+/// the upstreams that fail to fork, to fetch fork data or that stay rate limited or time out.
+/// Returns the result and the fork URL of the upstream that served it. Offline, the simulation
+/// runs on the offline state instead.
+async fn with_failover<T, F, Fut>(
+    config: &Config,
+    chain_id: u64,
+    block_number: Option<u64>,
+    mut simulation: F
+) -> Result<(T, String), Rejection>
+    where F: FnMut(ForkSource) -> Fut, Fut: Future<Output = Result<T, Rejection>>
+{
+    if let Some(offline_state) = &config.offline_state {
+        if offline_state.chain_id != chain_id {
+            return Err(warp::reject::custom(NoURLForChainIdError));
+        }
+        let value = simulation(ForkSource::Offline(Arc::clone(offline_state))).await?;
+        return Ok((value, OFFLINE_FORK_URL.to_string()));
+    }
+
+    let mut result = Err(NoURLForChainIdError.into());
+    let mut failed = Vec::new();
+    for fork_url in config.chains.upstreams(chain_id)? {
+        let key = pool_key(config, chain_id, block_number, &fork_url);
+        let (upstream, source) = match fork_source(config, chain_id, &fork_url, key.as_ref()) {
+            Ok(fork) => fork,
+            Err(err) => {
+                failed.push(fork_url);
+                result = Err(err.into());
+                continue;
+            }
+        };
+
+        let outcome = match source {
+            Ok(source) => simulation(source).await,
+            Err(err) => Err(err.into()),
+        };
+        let err = match outcome {
+            Ok(value) => {
+                mark_failed(config, &failed);
+                config.chains.mark_healthy(&fork_url);
+                return Ok((value, fork_url));
+            }
+            Err(err) => failover_error(err, &upstream)?,
+        };
+        log::warn!(target: "ts::api", "Upstream {} failed, trying the next one", fork_url_alias(&fork_url));
+        failed.push(fork_url);
+        if let Some(key) = &key {
+            config.backend_pool.evict(key);
+        }
+        result = Err(err);
+    }
+    result
+}
+
+/// Marks the upstreams that failed a request another upstream served. When all of them fail, it's
+/// more likely the request, like one for a block that doesn't exist yet, than the upstreams.
+fn mark_failed(config: &Config, failed: &[String]) {
+    for fork_url in failed {
+        config.chains.mark_failed(fork_url);
+    }
+}
+
+/// Key of the warm fork of `fork_url` in the backend pool. Only forks of a given block are pooled.
+/// None are with an RPC budget, as requests on a warm fork share its upstream requests, or while
+/// recording a cassette, which is written when forks are dropped.
+fn pool_key(config: &Config, chain_id: u64, block_number: Option<u64>, fork_url: &str) -> Option<BackendKey> {
+    let recording = config.rpc_cassette.as_ref().map_or(false, |cassette| cassette.mode == CassetteMode::Record);
+    if !config.backend_pool.is_enabled() || config.simulation_rpc_budget.is_some() || recording {
+        return None;
+    }
+    Some(BackendKey {
+        chain_id,
+        block_number: block_number?,
+        upstream: fork_url.to_string(),
+    })
+}
+
+/// What to run a simulation on `fork_url` from: the warm fork of the backend pool, a new warm fork
+/// that is added to the pool, or a new fork when the fork can't be pooled. Also returns the lease
+/// of the upstream the fork fetches its data through.
+fn fork_source(
+    config: &Config,
+    chain_id: u64,
+    fork_url: &str,
+    key: Option<&BackendKey>
+) -> Result<(Arc<UpstreamLease>, Result<ForkSource, ForkError>), UpstreamUnreachable> {
+    // Requests on a warm fork each have their own lease, so that they don't see or reset the
+    // failures of the others, nor the ones the pool checks
+    if let Some(fork) = key.and_then(|key| config.backend_pool.checkout(key)) {
+        let upstream = fork.upstream.checkout();
+        return Ok((Arc::clone(&upstream), Ok(ForkSource::Pooled(fork, upstream))));
+    }
+
+    let upstream = UpstreamLease::new(fork_url, chain_id, UpstreamPolicy::from_config(config))?;
+    let Some(key) = key else {
+        return Ok((Arc::clone(&upstream), Ok(ForkSource::Upstream(upstream))));
     };
+    match WarmFork::spawn(Arc::clone(&upstream), key.block_number) {
+        Ok(fork) => {
+            let fork = Arc::new(fork);
+            config.backend_pool.insert(key.clone(), Arc::clone(&fork));
+            let upstream = fork.upstream.checkout();
+            Ok((Arc::clone(&upstream), Ok(ForkSource::Pooled(fork, upstream))))
+        }
+        Err(err) => Ok((upstream, Err(err))),
+    }
+}
 
-    Ok(warp::reply::json(&response))
+/// Classifies the failure of a request on `upstream`: `Ok` with the error to report if the next
+/// upstream of the chain might serve it, `Err` with the error to answer with otherwise. Fork data
+/// failures are the ones the upstream proxy gave up on, archive upstreams might also have the
+/// historical state others don't.
+fn failover_error(err: Rejection, upstream: &UpstreamLease) -> Result<Rejection, Rejection> {
+    match upstream.failure() {
+        // Another upstream would use up the same budget, or miss the same cassette recording
+        Some(failure @ (UpstreamFailure::BudgetExceeded | UpstreamFailure::NotRecorded)) => {
+            Err(failure.into())
+        }
+        Some(failure) => Ok(failure.into()),
+        None if is_fork_failure(&err) => Ok(err),
+        None => Err(err),
+    }
+}
+
+/// Whether the upstream failed to fork, rather than the request.
+fn is_fork_failure(err: &Rejection) -> bool {
+    err.find::<FailedInstantiateFork>().is_some() ||
+        err.find::<UpstreamUnreachable>().is_some() ||
+        err.find::<UpstreamChainMismatch>().is_some() ||
+        err.find::<HistoricalStateUnavailable>().is_some()
+}
+
+/// Moves the forks of a stateful simulation whose upstream gave up on fork data to the next
+/// upstream of their chain, at the same block and with the same local changes. The request that
+/// hit the failure still fails, the next ones run on the new upstream.
+pub(crate) fn fail_over_session(evm: &mut Evm, config: &Config) {
+    for (chain_id, fork_url) in evm.failed_forks() {
+        let upstreams = config.chains.upstreams(chain_id).unwrap_or_default();
+        let Some(next_url) = upstreams.into_iter().find(|url| *url != fork_url) else {
+            continue;
+        };
+        let Ok(upstream) = UpstreamLease::new(&next_url, chain_id, UpstreamPolicy::from_config(config)) else {
+            continue;
+        };
+
+        match evm.switch_upstream(chain_id, upstream) {
+            Ok(()) => {
+                config.chains.mark_failed(&fork_url);
+                config.chains.mark_healthy(&next_url);
+                log::warn!(
+                    target: "ts::api",
+                    "Upstream {} failed, moved a stateful simulation to {}",
+                    fork_url_alias(&fork_url),
+                    fork_url_alias(&next_url)
+                );
+            }
+            Err(err) => {
+                log::warn!(target: "ts::api", "Failed to move a stateful simulation to {}: {err:?}", fork_url_alias(&next_url));
+            }
+        }
+    }
+}
+
+/// Reports why the upstream failed to serve fork data, if that is what made the simulation fail.
+fn upstream_error(evm: &Evm, err: EvmError) -> Rejection {
+    match evm.upstream_failure() {
+        Some(failure) => failure.into(),
+        None if err.is_historical_state_unavailable() => warp::reject::custom(HistoricalStateUnavailable),
+        None => err.into(),
+    }
+}
+
+/// Reports the host of the upstream that served a simulation in the `X-Upstream` header.
+fn with_upstream(reply: Json, fork_url: &str) -> WithHeader<Json> {
+    warp::reply::with_header(reply, "X-Upstream", fork_url_alias(fork_url))
 }
 
 pub async fn simulate_stateful_end(
     param: Uuid,
     state: Arc<SharedSimulationState>
 ) -> Result<Json, Rejection> {
-    if state.evms.contains_key(&param) {
-        state.evms.remove(&param);
+    if state.remove_session(&param) {
         let response = StatefulSimulationEndResponse { success: true };
         Ok(warp::reply::json(&response))
     } else {
@@ -262,58 +768,331 @@ pub async fn simulate_stateful_end(
     }
 }
 
+pub async fn simulate_stateful_info(
+    param: Uuid,
+    evm: OwnedMutexGuard<Evm>,
+    state: Arc<SharedSimulationState>
+) -> Result<Json, Rejection> {
+    let (_, info) = state.peek_session(&param)?;
+    let response = stateful_simulation_info(param, &info, &evm);
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn simulate_stateful_list(state: Arc<SharedSimulationState>) -> Result<Json, Rejection> {
+    let ids: Vec<Uuid> = state.sessions.iter().map(|entry| *entry.key()).collect();
+
+    let mut response = Vec::with_capacity(ids.len());
+    for id in ids {
+        // Sessions can expire while the list is built, and busy ones would hold up the list
+        if let Ok((evm, info)) = state.peek_session(&id) {
+            if let Ok(evm) = evm.try_lock() {
+                response.push(stateful_simulation_info(id, &info, &evm));
+            }
+        }
+    }
+
+    Ok(warp::reply::json(&response))
+}
+
+fn stateful_simulation_info(
+    id: Uuid,
+    info: &SessionInfo,
+    evm: &Evm
+) -> StatefulSimulationInfoResponse {
+    StatefulSimulationInfoResponse {
+        stateful_simulation_id: id,
+        chain_id: evm.get_chain_id().as_u64(),
+        fork_url: evm.get_fork_url_alias(),
+        fork_block_number: evm.fork_block_number,
+        block_number: evm.get_block().as_u64(),
+        block_timestamp: evm.get_block_timestamp().as_u64(),
+        created_at: to_unix_timestamp(info.created_at),
+        last_used_at: to_unix_timestamp(info.last_used),
+        transaction_count: evm.transaction_count,
+        approximate_memory_size: evm.approximate_memory_size(),
+    }
+}
+
+pub async fn simulate_stateful_snapshot(mut evm: OwnedMutexGuard<Evm>) -> Result<Json, Rejection> {
+    let snapshot_id = evm.snapshot();
+
+    let response = StatefulSimulationSnapshotResponse { snapshot_id };
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn simulate_stateful_revert(
+    param: Uuid,
+    mut evm: OwnedMutexGuard<Evm>,
+    revert_request: StatefulSimulationRevertRequest,
+    state: Arc<SharedSimulationState>
+) -> Result<Json, Rejection> {
+    let keep = revert_request.keep.unwrap_or(true);
+
+    if !evm.revert(revert_request.snapshot_id.into(), keep) {
+        return Err(warp::reject::custom(SnapshotNotFound));
+    }
+    state.persist_session(&param, &mut evm);
+
+    let response = StatefulSimulationRevertResponse { success: true };
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn simulate_stateful_state(
+    param: Uuid,
+    mut evm: OwnedMutexGuard<Evm>,
+    state_request: StatefulSimulationStateRequest,
+    state: Arc<SharedSimulationState>
+) -> Result<Json, Rejection> {
+    evm.reset_upstream_usage();
+
+    apply_state_overrides(&mut evm, state_request.state_overrides)?;
+    if let Some(block_number) = state_request.block_number {
+        evm.set_block(block_number).await?;
+    }
+    if let Some(timestamp) = state_request.block_timestamp {
+        evm.set_block_timestamp(timestamp).await?;
+    }
+    state.persist_session(&param, &mut evm);
+
+    let response = StatefulSimulationStateResponse { success: true };
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn simulate_stateful_roll_fork(
+    param: Uuid,
+    mut evm: OwnedMutexGuard<Evm>,
+    roll_fork_request: StatefulSimulationRollForkRequest,
+    state: Arc<SharedSimulationState>
+) -> Result<Json, Rejection> {
+    evm.reset_upstream_usage();
+
+    let block_number = match roll_fork_request.block_number {
+        Some(block_number) => block_number,
+        None => evm.get_latest_block_number().await?,
+    };
+    if block_number < evm.fork_block_number {
+        return Err(warp::reject::custom(InvalidBlockNumbersError()));
+    }
+
+    evm.roll_fork(block_number)?;
+    state.persist_session(&param, &mut evm);
+
+    let response = StatefulSimulationRollForkResponse { fork_block_number: block_number };
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn simulate_stateful_account(
+    evm: OwnedMutexGuard<Evm>,
+    address: Address
+) -> Result<Json, Rejection> {
+    evm.reset_upstream_usage();
+
+    let response = StatefulSimulationAccountResponse {
+        balance: evm.get_balance(address)?,
+        nonce: evm.get_nonce(address)?,
+        code: evm.get_code(address)?,
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn simulate_stateful_storage(
+    evm: OwnedMutexGuard<Evm>,
+    address: Address,
+    slot: PermissiveUint
+) -> Result<Json, Rejection> {
+    evm.reset_upstream_usage();
+    let value = evm.get_storage_at(address, slot.into())?;
+
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    let response = StatefulSimulationStorageResponse { value: bytes.into() };
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn simulate_stateful_call(
+    mut evm: OwnedMutexGuard<Evm>,
+    transaction: SimulationRequest,
+    config: Config
+) -> Result<Json, Rejection> {
+    evm.reset_upstream_usage();
+
+    if !evm.has_chain(transaction.chain_id) {
+        return Err(warp::reject::custom(IncorrectChainIdError()));
+    }
+    let primary_chain_id = evm.get_chain_id().as_u64();
+    evm.select_chain(transaction.chain_id)?;
+
+    // State overrides of the call must not outlive it
+    let checkpoint = transaction.state_overrides.is_some().then(|| evm.checkpoint());
+    let response = run(&mut evm, transaction, false).await;
+    if let Some(checkpoint) = checkpoint {
+        evm.restore(checkpoint);
+    }
+    evm.select_chain(primary_chain_id)?;
+    if response.is_err() {
+        fail_over_session(&mut evm, &config);
+    }
+
+    Ok(warp::reply::json(&response?))
+}
+
+pub async fn simulate_stateful_transactions(evm: OwnedMutexGuard<Evm>) -> Result<Json, Rejection> {
+    let transactions: Vec<&SessionTransaction> = evm.transactions.iter().map(Arc::as_ref).collect();
+    Ok(warp::reply::json(&transactions))
+}
+
+pub async fn simulate_stateful_logs(evm: OwnedMutexGuard<Evm>, filter: Filter) -> Result<Json, Rejection> {
+    // The logs of the forked chain are fetched without holding the simulation
+    let query = evm.logs_query(filter)?;
+    drop(evm);
+    let logs = query.fetch().await?;
+
+    Ok(warp::reply::json(&logs))
+}
+
+pub async fn simulate_stateful_mine(
+    param: Uuid,
+    mut evm: OwnedMutexGuard<Evm>,
+    config: Config,
+    state: Arc<SharedSimulationState>
+) -> Result<Json, Rejection> {
+    let block_time = config.chains.block_time(evm.get_chain_id().as_u64());
+    let block = evm.mine(block_time);
+    state.persist_session(&param, &mut evm);
+
+    Ok(warp::reply::json(&block))
+}
+
 pub async fn simulate_stateful(
     param: Uuid,
-    transactions: Vec<SimulationRequest>,
+    mut evm: OwnedMutexGuard<Evm>,
+    bundle: SimulationBundleRequest,
+    config: Config,
     state: Arc<SharedSimulationState>
 ) -> Result<Json, Rejection> {
-    let first_chain_id = transactions[0].chain_id;
+    let SimulationBundleRequest { transactions, revert_policy } = bundle;
 
     let response = Vec::with_capacity(transactions.len());
 
-    let evm_ref_mut: RefMut<'_, Uuid, Arc<Mutex<Evm>>> = state.evms
-        .get_mut(&param)
-        .ok_or_else(warp::reject::not_found)?;
+    evm.reset_upstream_usage();
 
-    let evm = evm_ref_mut.value();
-    let mut evm = evm.lock().await;
-
-    if evm.get_chain_id() != Uint::from(first_chain_id) {
+    // Sessions only run on the chains they were created with
+    if transactions.iter().any(|transaction| !evm.has_chain(transaction.chain_id)) {
         return Err(warp::reject::custom(IncorrectChainIdError()));
     }
 
-    let response = process_transactions(&mut evm, transactions, response).await?;
+    let chain_ids: Vec<u64> = transactions.iter().map(|transaction| transaction.chain_id).collect();
+    let primary_chain_id = evm.get_chain_id().as_u64();
+    let response = process_transactions(&mut evm, &config, transactions, revert_policy, response).await;
+    evm.select_chain(primary_chain_id)?;
+    if response.is_err() {
+        fail_over_session(&mut evm, &config);
+    }
+    state.persist_session(&param, &mut evm);
+    let response = response?;
+
+    if evm.forks.is_empty() {
+        return Ok(warp::reply::json(&response));
+    }
+
+    // Sessions spanning several chains answer with a single result for the whole flow
+    let response = MultiChainSimulationResponse {
+        success: response.iter().all(|result| result.success),
+        gas_used: response.iter().map(|result| result.gas_used).sum(),
+        results: chain_ids
+            .into_iter()
+            .zip(response)
+            .map(|(chain_id, simulation)| ChainSimulationResponse { chain_id, simulation })
+            .collect(),
+    };
 
     Ok(warp::reply::json(&response))
 }
 
 async fn process_transactions(
     evm: &mut Evm,
+    config: &Config,
     transactions: Vec<SimulationRequest>,
+    revert_policy: RevertPolicy,
     mut response: Vec<SimulationResponse>,
 ) -> Result<Vec<SimulationResponse>, Rejection> {
-    let first_chain_id = transactions[0].chain_id;
-    let first_block_number = transactions[0].block_number;
+    // Atomic bundles are rolled back as a whole, including in stateful sessions
+    let checkpoint = (revert_policy == RevertPolicy::Atomic).then(|| evm.checkpoint());
+
+    match execute_transactions(evm, config, transactions, revert_policy, &mut response).await {
+        Ok(reverted) => {
+            if let (Some(checkpoint), true) = (checkpoint, reverted) {
+                evm.restore(checkpoint);
+            }
+            Ok(response)
+        }
+        Err(err) => {
+            if let Some(checkpoint) = checkpoint {
+                evm.restore(checkpoint);
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Runs the bundle and returns whether it stopped on a transaction that was not allowed to revert.
+async fn execute_transactions(
+    evm: &mut Evm,
+    config: &Config,
+    transactions: Vec<SimulationRequest>,
+    revert_policy: RevertPolicy,
+    response: &mut Vec<SimulationResponse>,
+) -> Result<bool, Rejection> {
+    let mut block_gas_used = 0;
 
     for transaction in transactions {
-        if transaction.chain_id != first_chain_id {
+        // Stateful simulations can hold a fork per chain, transactions run on the fork of theirs
+        if !evm.has_chain(transaction.chain_id) {
             return Err(warp::reject::custom(MultipleChainIdsError()));
         }
-        if
-            transaction.block_number != first_block_number ||
-            transaction.block_number.unwrap() != evm.get_block().as_u64()
-            {
-                let tx_block = transaction.block_number.expect("Transaction has no block number");
-                if transaction.block_number < first_block_number || tx_block < evm.get_block().as_u64() {
-                    return Err(warp::reject::custom(InvalidBlockNumbersError()));
-                }
-                evm.set_block(tx_block).await.expect("Failed to set block number");
-                evm.set_block_timestamp(evm.get_block_timestamp().as_u64() + 12).await.expect(
-                    "Failed to set block timestamp"
-                );
+        if evm.get_chain_id() != Uint::from(transaction.chain_id) {
+            evm.select_chain(transaction.chain_id)?;
+            block_gas_used = 0;
+        }
+        let block_time = config.chains.block_time(transaction.chain_id);
+
+        let current_block = evm.get_block().as_u64();
+        let advanced_block = current_block + transaction.advance_blocks.unwrap_or_default();
+        let target_block = match transaction.block_number {
+            Some(tx_block) if tx_block < advanced_block => {
+                return Err(warp::reject::custom(InvalidBlockNumbersError()));
             }
-            response.push(run(evm, transaction, true).await?);
+            Some(tx_block) => tx_block,
+            None => advanced_block,
+        };
+        if target_block > current_block {
+            evm.advance_blocks(target_block - current_block, block_time, block_gas_used);
+            block_gas_used = 0;
+        }
+        if let Some(advance_time) = transaction.advance_time {
+            let timestamp = evm.get_block_timestamp().as_u64() + advance_time;
+            evm.set_block_timestamp(timestamp).await?;
         }
+        if let Some(timestamp) = transaction.block_timestamp {
+            evm.set_block_timestamp(timestamp).await?;
+        }
+
+        let can_revert = transaction.can_revert.unwrap_or_default();
+        let result = run(evm, transaction, true).await?;
+        block_gas_used += result.gas_used;
+        let stop = !result.success && !can_revert && revert_policy != RevertPolicy::Continue;
+        response.push(result);
+        if stop {
+            return Ok(true);
+        }
+    }
 
-        Ok(response)
+    Ok(false)
 }
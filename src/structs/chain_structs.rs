@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicUsize;
+use std::time::Instant;
+
+use dashmap::DashMap;
+use serde::{ Deserialize, Serialize };
+
+#[derive(Debug, Default)]
+pub struct ChainRegistry {
+    pub chains: BTreeMap<u64, ChainConfig>,
+    pub health: UpstreamHealth,
+}
+
+#[derive(Debug, Default)]
+pub struct UpstreamHealth {
+    pub next: AtomicUsize,
+    pub failures: DashMap<String, Instant>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    pub name: String,
+    pub rpc_urls: Vec<String>,
+    #[serde(default)]
+    pub native_currency: NativeCurrency,
+    #[serde(default = "default_block_time")]
+    pub block_time: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NativeCurrency {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl Default for NativeCurrency {
+    fn default() -> Self {
+        NativeCurrency {
+            name: "Ether".to_string(),
+            symbol: "ETH".to_string(),
+            decimals: 18,
+        }
+    }
+}
+
+pub(crate) fn default_block_time() -> u64 {
+    12
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainResponse {
+    pub chain_id: u64,
+    pub name: String,
+    pub native_currency: NativeCurrency,
+    pub block_time: u64,
+}
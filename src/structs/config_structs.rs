@@ -1,8 +1,30 @@
+use std::sync::Arc;
+
+use super::{ BackendPool, Cassette, ChainRegistry, OfflineState, SimulationWorkers };
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub port: u16,
-    pub fork_url: Option<String>,
+    pub chains: Arc<ChainRegistry>,
+    pub offline_state: Option<Arc<OfflineState>>,
     pub etherscan_key: Option<String>,
     pub api_key: Option<String>,
+    pub admin_api_key: Option<String>,
     pub max_request_size: u64,
+    pub session_idle_ttl: Option<u64>,
+    pub session_max_ttl: Option<u64>,
+    pub max_sessions: Option<usize>,
+    pub max_sessions_per_api_key: Option<usize>,
+    pub session_memory_limit: Option<usize>,
+    pub session_reaper_interval: u64,
+    pub session_store_dir: Option<String>,
+    pub session_store_retention: Option<u64>,
+    pub upstream_retries: u32,
+    pub upstream_backoff: u64,
+    pub upstream_timeout: u64,
+    pub upstream_rate_limit: Option<u32>,
+    pub simulation_rpc_budget: Option<u64>,
+    pub rpc_cassette: Option<Arc<Cassette>>,
+    pub backend_pool: Arc<BackendPool>,
+    pub simulation_workers: Arc<SimulationWorkers>,
 }
\ No newline at end of file
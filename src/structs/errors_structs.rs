@@ -25,6 +25,18 @@ pub struct InvalidBlockNumbersError();
 #[derive(Debug)]
 pub struct StateNotFound();
 
+#[derive(Debug)]
+pub struct SnapshotNotFound;
+
+#[derive(Debug)]
+pub struct SessionExpired;
+
+#[derive(Debug)]
+pub struct SessionLimitReached;
+
+#[derive(Debug)]
+pub struct UnauthorizedError;
+
 #[derive(Debug)]
 pub struct OverrideError;
 
@@ -34,5 +46,47 @@ pub struct EvmError(pub Report);
 #[derive(Debug)]
 pub struct FailedInstantiateFork;
 
+/// Why forking an upstream failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForkError {
+    BlockNotFound,
+    HistoricalStateUnavailable,
+    ChainMismatch,
+    Failed,
+}
+
+#[derive(Debug)]
+pub struct BlockNotFound;
+
+#[derive(Debug)]
+pub struct HistoricalStateUnavailable;
+
+#[derive(Debug)]
+pub struct UpstreamUnreachable;
+
+#[derive(Debug)]
+pub struct UpstreamChainMismatch;
+
+#[derive(Debug)]
+pub struct UpstreamNotRecorded;
+
+#[derive(Debug)]
+pub struct SimulationQueueFull;
+
+#[derive(Debug)]
+pub struct FailedToSetBlockTimestamp;
+
+#[derive(Debug)]
+pub struct InvalidRawTransactionError;
+
+#[derive(Debug)]
+pub struct ContractCreationNotSupported;
+
+#[derive(Debug)]
+pub struct UpstreamRateLimited;
+
+#[derive(Debug)]
+pub struct UpstreamTimeout;
+
 #[derive(Debug)]
-pub struct FailedToSetBlockTimestamp;
\ No newline at end of file
+pub struct UpstreamBudgetExceeded;
@@ -1,12 +1,18 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use ethers::abi::{ Address, Hash, Uint };
 use ethers::core::types::Log;
+use ethers::providers::{ Http, Provider };
 use ethers::types::transaction::eip2930::AccessList;
-use ethers::types::Bytes;
-use foundry_evm::executor::Executor;
+use ethers::types::{ Block, Bytes, Filter, Transaction, TransactionReceipt };
+use foundry_evm::executor::{ Backend, Executor };
 use foundry_evm::trace::identifier::EtherscanIdentifier;
 use foundry_evm::trace::{ CallTraceArena, CallTraceDecoder };
 use revm::interpreter::InstructionResult;
+use revm::primitives::{ BlockEnv, Env };
+use serde::Serialize;
+
+use super::{ OfflineState, UpstreamLease, WarmFork };
 
 #[derive(Debug, Clone)]
 pub struct CallRawRequest {
@@ -28,6 +34,7 @@ pub struct CallRawResult {
     pub exit_reason: InstructionResult,
     pub return_data: Bytes,
     pub formatted_trace: Option<String>,
+    pub transaction_hash: Option<Hash>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -40,4 +47,86 @@ pub struct Evm {
     pub executor: Executor,
     pub decoder: CallTraceDecoder,
     pub etherscan_identifier: Option<EtherscanIdentifier>,
+    pub snapshots: HashMap<Uint, EvmCheckpoint>,
+    pub next_snapshot_id: Uint,
+    pub fork_url: String,
+    pub fork_block_number: u64,
+    pub gas_limit: u64,
+    pub transaction_count: u64,
+    /// Transactions committed in a stateful simulation, shared with the snapshots taken since.
+    pub transactions: Vec<Arc<SessionTransaction>>,
+    /// Whether committed transactions are recorded in `transactions`, only stateful simulations
+    /// look them up.
+    pub record_transactions: bool,
+    pub blocks: Vec<Block<Hash>>,
+    pub written_slots: WrittenSlots,
+    /// Seconds added to the block timestamp with `evm_increaseTime`.
+    pub time_offset: u64,
+    pub forks: HashMap<u64, InactiveFork>,
+    pub upstreams: Vec<Arc<UpstreamLease>>,
+}
+
+/// Local changes to an account, as the overrides that apply them to another fork block.
+pub struct AccountChanges {
+    pub address: Address,
+    pub balance: Option<Uint>,
+    pub nonce: Option<u64>,
+    pub code: Option<Bytes>,
+    pub storage: StorageOverride,
+}
+
+/// Storage slots written by transactions or overrides, by account, with the value each slot had
+/// before it was first written.
+pub type WrittenSlots = HashMap<Address, HashMap<Uint, Uint>>;
+
+/// What an EVM starts from: a fork of an upstream through the upstream proxy, a warm fork of the
+/// backend pool, or the offline state.
+#[derive(Debug, Clone)]
+pub enum ForkSource {
+    Upstream(Arc<UpstreamLease>),
+    /// A warm fork of the backend pool, and the lease of its upstream for this request.
+    Pooled(Arc<WarmFork>, Arc<UpstreamLease>),
+    Offline(Arc<OfflineState>),
+}
+
+#[derive(Debug, Clone)]
+pub struct InactiveFork {
+    pub fork_id: Uint,
+    pub fork_url: String,
+    pub fork_block_number: u64,
+    pub block: BlockEnv,
+    pub transactions: Vec<Arc<SessionTransaction>>,
+    pub blocks: Vec<Block<Hash>>,
+    pub written_slots: WrittenSlots,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionTransaction {
+    pub transaction: Transaction,
+    pub receipt: TransactionReceipt,
+    #[serde(skip)]
+    pub block: BlockEnv,
+    pub return_data: Bytes,
+    pub exit_reason: InstructionResult,
+}
+
+#[derive(Clone)]
+pub struct EvmCheckpoint {
+    pub backend: Backend,
+    pub env: Env,
+    pub fork_url: String,
+    pub fork_block_number: u64,
+    pub transaction_count: u64,
+    pub transactions: Vec<Arc<SessionTransaction>>,
+    pub blocks: Vec<Block<Hash>>,
+    pub written_slots: WrittenSlots,
+    pub time_offset: u64,
+    pub forks: HashMap<u64, InactiveFork>,
+}
+
+/// Logs of an EVM, along with the request for the logs of its forked chain when some are needed.
+pub struct LogsQuery {
+    pub logs: Vec<Log>,
+    pub upstream: Option<(Provider<Http>, Filter)>,
 }
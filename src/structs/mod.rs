@@ -8,4 +8,28 @@ pub mod errors_structs;
 pub use errors_structs::*;
 
 pub mod config_structs;
-pub use config_structs::*;
\ No newline at end of file
+pub use config_structs::*;
+
+pub mod chain_structs;
+pub use chain_structs::*;
+
+pub mod session_structs;
+pub use session_structs::*;
+
+pub mod state_structs;
+pub use state_structs::*;
+
+pub mod rpc_structs;
+pub use rpc_structs::*;
+
+pub mod upstream_structs;
+pub use upstream_structs::*;
+
+pub mod offline_structs;
+pub use offline_structs::*;
+
+pub mod pool_structs;
+pub use pool_structs::*;
+
+pub mod worker_structs;
+pub use worker_structs::*;
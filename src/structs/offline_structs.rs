@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+
+use ethers::abi::Address;
+use ethers::types::{ Bytes, H256 };
+use serde::Deserialize;
+
+use super::{ PermissiveUint, SerializableState };
+
+/// State offline simulations start from, instead of forking an upstream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineState {
+    pub chain_id: u64,
+    pub state: SerializableState,
+}
+
+/// Genesis file in the format of geth's `genesis.json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Genesis {
+    #[serde(default)]
+    pub config: GenesisConfig,
+    pub number: Option<PermissiveUint>,
+    pub timestamp: Option<PermissiveUint>,
+    pub gas_limit: Option<PermissiveUint>,
+    pub base_fee_per_gas: Option<PermissiveUint>,
+    pub difficulty: Option<PermissiveUint>,
+    pub coinbase: Option<Address>,
+    pub alloc: BTreeMap<Address, GenesisAccount>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenesisConfig {
+    pub chain_id: u64,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        GenesisConfig { chain_id: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GenesisAccount {
+    #[serde(default)]
+    pub balance: Option<PermissiveUint>,
+    #[serde(default)]
+    pub nonce: Option<PermissiveUint>,
+    #[serde(default)]
+    pub code: Bytes,
+    #[serde(default)]
+    pub storage: BTreeMap<H256, H256>,
+}
@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use foundry_evm::executor::Backend;
+use revm::primitives::Env;
+
+use super::UpstreamLease;
+
+/// Forked backends kept warm for requests at the same block to start from, so that they don't
+/// fetch the fork environment again and share the accounts and storage already fetched.
+#[derive(Debug)]
+pub struct BackendPool {
+    pub size: usize,
+    pub ttl: Duration,
+    pub forks: Mutex<HashMap<BackendKey, PooledFork>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BackendKey {
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub upstream: String,
+}
+
+#[derive(Debug)]
+pub struct PooledFork {
+    pub fork: Arc<WarmFork>,
+    pub last_used: Instant,
+}
+
+/// A backend forked at a block that no transaction ran on. EVMs start from clones of it, which
+/// share its cache of upstream data but not their changes.
+pub struct WarmFork {
+    pub upstream: Arc<UpstreamLease>,
+    pub backend: Backend,
+    pub env: Env,
+}
@@ -0,0 +1,59 @@
+use ethers::abi::{ Address, Uint };
+use ethers::types::Bytes;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+use crate::structs::LogsQuery;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum JsonRpcPayload {
+    Single(JsonRpcRequest),
+    Batch(Vec<JsonRpcRequest>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonRpcRequest {
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+}
+
+/// A response, or the logs query answering an `eth_getLogs` request, which is only sent once the
+/// simulation is released.
+pub enum PendingRpcResponse {
+    Ready(JsonRpcResponse),
+    Logs(Value, LogsQuery),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTransactionRequest {
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub gas: Option<Uint>,
+    pub value: Option<Uint>,
+    #[serde(alias = "input")]
+    pub data: Option<Bytes>,
+    pub nonce: Option<Uint>,
+}
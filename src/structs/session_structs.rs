@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::time::{ Duration, Instant };
+
+use dashmap::DashMap;
+use serde::{ Deserialize, Serialize };
+use uuid::Uuid;
+
+use super::{ SerializableState, StatefulSimulationRequest };
+
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    /// Hash of the API key the session was created with, which it is limited with.
+    pub api_key_id: Option<String>,
+    pub created_at: Instant,
+    pub last_used: Instant,
+    /// Approximate memory size of the session when it was last changed or checked by the reaper.
+    pub memory_size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    pub dir: PathBuf,
+    pub retention: Option<Duration>,
+    /// Saves of each session, for a save that finishes late not to overwrite a newer one.
+    pub written_versions: Arc<DashMap<Uuid, SessionVersions>>,
+    pub next_version: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Default)]
+pub struct SessionVersions {
+    /// Version of the last save written, `u64::MAX` once the session was removed.
+    pub written: u64,
+    /// Saves not written yet.
+    pub pending: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistedSession {
+    pub id: Uuid,
+    /// Stored instead of the API key, so that session files don't hold keys.
+    #[serde(default)]
+    pub api_key_id: Option<String>,
+    pub created_at: u64,
+    pub last_used: u64,
+    pub simulation: StatefulSimulationRequest,
+    pub state: SerializableState,
+    /// State of the forks of the other chains, which `simulation` lists.
+    #[serde(default)]
+    pub forks: Vec<PersistedFork>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistedFork {
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub state: SerializableState,
+}
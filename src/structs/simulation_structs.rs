@@ -2,12 +2,14 @@ use std::collections::HashMap;
 use ethers::abi::{ Address, Hash, Uint };
 use ethers::core::types::Log;
 use ethers::types::transaction::eip2930::AccessList;
-use ethers::types::Bytes;
+use ethers::types::{ BlockNumber, Bytes, U64 };
 use foundry_evm::CallKind;
 use revm::interpreter::InstructionResult;
 use serde::{ Deserialize, Serialize };
 use uuid::Uuid;
 
+use super::SerializableState;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SimulationRequest {
@@ -22,6 +24,25 @@ pub struct SimulationRequest {
     pub block_timestamp: Option<u64>,
     pub state_overrides: Option<HashMap<Address, StateOverride>>,
     pub format_trace: Option<bool>,
+    pub can_revert: Option<bool>,
+    pub advance_blocks: Option<u64>,
+    pub advance_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationBundleRequest {
+    pub transactions: Vec<SimulationRequest>,
+    pub revert_policy: RevertPolicy,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RevertPolicy {
+    #[default]
+    Continue,
+    StopOnFirstRevert,
+    Atomic,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -36,6 +57,67 @@ pub struct SimulationResponse {
     pub logs: Vec<Log>,
     pub exit_reason: InstructionResult,
     pub return_data: Bytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_hash: Option<Hash>,
+}
+
+/// Result of a bundle in a stateful simulation holding forks of several chains.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiChainSimulationResponse {
+    pub success: bool,
+    pub gas_used: u64,
+    pub results: Vec<ChainSimulationResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainSimulationResponse {
+    pub chain_id: u64,
+    #[serde(flatten)]
+    pub simulation: SimulationResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallBundleRequest {
+    pub txs: Vec<Bytes>,
+    pub block_number: U64,
+    pub state_block_number: BlockNumber,
+    pub timestamp: Option<u64>,
+    pub chain_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallBundleResponse {
+    pub bundle_gas_price: String,
+    pub bundle_hash: Hash,
+    pub coinbase_diff: String,
+    pub eth_sent_to_coinbase: String,
+    pub gas_fees: String,
+    pub results: Vec<CallBundleTransactionResult>,
+    pub state_block_number: u64,
+    pub total_gas_used: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallBundleTransactionResult {
+    pub coinbase_diff: String,
+    pub eth_sent_to_coinbase: String,
+    pub from_address: Address,
+    pub gas_fees: String,
+    pub gas_price: String,
+    pub gas_used: u64,
+    pub to_address: Address,
+    pub tx_hash: Hash,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Bytes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert: Option<Bytes>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +127,22 @@ pub struct StatefulSimulationRequest {
     pub gas_limit: u64,
     pub block_number: Option<u64>,
     pub block_timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forks: Option<Vec<StatefulSimulationForkRequest>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatefulSimulationForkRequest {
+    pub chain_id: u64,
+    pub block_number: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatefulSimulationLoadRequest {
+    #[serde(flatten)]
+    pub simulation: StatefulSimulationRequest,
+    pub state: SerializableState,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -53,11 +151,81 @@ pub struct StatefulSimulationResponse {
     pub stateful_simulation_id: Uuid,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatefulSimulationInfoResponse {
+    pub stateful_simulation_id: Uuid,
+    pub chain_id: u64,
+    pub fork_url: String,
+    pub fork_block_number: u64,
+    pub block_number: u64,
+    pub block_timestamp: u64,
+    pub created_at: u64,
+    pub last_used_at: u64,
+    pub transaction_count: u64,
+    pub approximate_memory_size: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StatefulSimulationEndResponse {
     pub success: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatefulSimulationSnapshotResponse {
+    pub snapshot_id: Uint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatefulSimulationRevertRequest {
+    pub snapshot_id: PermissiveUint,
+    pub keep: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatefulSimulationRevertResponse {
+    pub success: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatefulSimulationStateRequest {
+    pub state_overrides: Option<HashMap<Address, StateOverride>>,
+    pub block_number: Option<u64>,
+    pub block_timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatefulSimulationStateResponse {
+    pub success: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatefulSimulationRollForkRequest {
+    pub block_number: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatefulSimulationRollForkResponse {
+    pub fork_block_number: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatefulSimulationAccountResponse {
+    pub balance: Uint,
+    pub nonce: u64,
+    pub code: Bytes,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatefulSimulationStorageResponse {
+    pub value: Hash,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StateOverride {
     pub balance: Option<PermissiveUint>,
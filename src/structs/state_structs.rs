@@ -0,0 +1,20 @@
+use std::collections::BTreeMap;
+use ethers::abi::{ Address, Uint };
+use ethers::types::Bytes;
+use revm::primitives::BlockEnv;
+use serde::{ Deserialize, Serialize };
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SerializableState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<BlockEnv>,
+    pub accounts: BTreeMap<Address, SerializableAccountRecord>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SerializableAccountRecord {
+    pub nonce: u64,
+    pub balance: Uint,
+    pub code: Bytes,
+    pub storage: BTreeMap<Uint, Uint>,
+}
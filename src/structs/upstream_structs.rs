@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{ AtomicBool, AtomicU64, AtomicUsize };
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use dashmap::DashMap;
+use ethers::providers::Http;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::JsonRpcError;
+
+#[derive(Debug, Clone)]
+pub struct UpstreamPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+    pub timeout: Duration,
+    pub rate_limit: Option<u32>,
+    pub budget: Option<u64>,
+    pub cassette: Option<Arc<Cassette>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpstreamFailure {
+    RateLimited,
+    Timeout,
+    BudgetExceeded,
+    Unreachable,
+    NotRecorded,
+}
+
+#[derive(Debug)]
+pub struct UpstreamRoute {
+    pub url: String,
+    pub chain_id: u64,
+    pub provider: Http,
+    pub policy: UpstreamPolicy,
+    pub calls: AtomicU64,
+    /// Latest request the proxy gave up on, and how many it gave up on so far.
+    pub failure: Mutex<Option<UpstreamFailure>>,
+    pub failures: AtomicU64,
+    pub leases: AtomicUsize,
+}
+
+/// A fork's route through the upstream proxy, removed from the proxy when its last lease is
+/// dropped. Each lease only reports the failures since it was taken or reset.
+#[derive(Debug)]
+pub struct UpstreamLease {
+    pub id: Uuid,
+    pub route: Arc<UpstreamRoute>,
+    pub failures_seen: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct UpstreamProxy {
+    pub addr: SocketAddr,
+    pub routes: Arc<DashMap<Uuid, Arc<UpstreamRoute>>>,
+    pub next_request_at: Arc<DashMap<String, Instant>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+/// Upstream JSON-RPC requests and their responses, recorded to be replayed without upstreams.
+#[derive(Debug)]
+pub struct Cassette {
+    pub path: PathBuf,
+    pub mode: CassetteMode,
+    pub interactions: Mutex<BTreeMap<String, CassetteInteraction>>,
+    pub dirty: AtomicBool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CassetteInteraction {
+    pub chain_id: u64,
+    pub method: String,
+    pub params: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CassetteFile {
+    pub interactions: Vec<CassetteInteraction>,
+}
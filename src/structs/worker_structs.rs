@@ -0,0 +1,14 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+/// Threads that EVMs are built and run on, off the server's runtime threads. Requests wait in a
+/// queue for a free worker.
+#[derive(Debug)]
+pub struct SimulationWorkers {
+    pub workers: usize,
+    pub queue_size: Option<usize>,
+    pub permits: Arc<Semaphore>,
+    pub queued: AtomicUsize,
+}
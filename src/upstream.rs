@@ -0,0 +1,351 @@
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use dashmap::DashMap;
+use ethers::providers::{ Http, HttpClientError, JsonRpcClient };
+use serde_json::Value;
+use uuid::Uuid;
+use warp::{ Filter, Rejection };
+
+use crate::structs::{
+    Config,
+    JsonRpcError,
+    JsonRpcPayload,
+    JsonRpcRequest,
+    JsonRpcResponse,
+    CassetteMode,
+    UpstreamBudgetExceeded,
+    UpstreamFailure,
+    UpstreamLease,
+    UpstreamNotRecorded,
+    UpstreamPolicy,
+    UpstreamProxy,
+    UpstreamRateLimited,
+    UpstreamRoute,
+    UpstreamTimeout,
+    UpstreamUnreachable,
+};
+
+/// Code of the errors the proxy answers with once it gives up on a request. It is not one that
+/// foundry retries on.
+const UPSTREAM_ERROR: i64 = -32000;
+
+static PROXY: Mutex<Option<&'static UpstreamProxy>> = Mutex::new(None);
+
+impl UpstreamPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        UpstreamPolicy {
+            retries: config.upstream_retries,
+            backoff: Duration::from_millis(config.upstream_backoff),
+            timeout: Duration::from_secs(config.upstream_timeout),
+            rate_limit: config.upstream_rate_limit,
+            budget: config.simulation_rpc_budget,
+            cassette: config.rpc_cassette.clone(),
+        }
+    }
+}
+
+impl From<UpstreamFailure> for Rejection {
+    fn from(failure: UpstreamFailure) -> Self {
+        match failure {
+            UpstreamFailure::RateLimited => warp::reject::custom(UpstreamRateLimited),
+            UpstreamFailure::Timeout => warp::reject::custom(UpstreamTimeout),
+            UpstreamFailure::BudgetExceeded => warp::reject::custom(UpstreamBudgetExceeded),
+            UpstreamFailure::Unreachable => warp::reject::custom(UpstreamUnreachable),
+            UpstreamFailure::NotRecorded => warp::reject::custom(UpstreamNotRecorded),
+        }
+    }
+}
+
+impl UpstreamLease {
+    /// Routes a fork of `url`, an upstream of `chain_id`, through the upstream proxy, which is
+    /// started on first use.
+    pub fn new(url: &str, chain_id: u64, policy: UpstreamPolicy) -> Result<Arc<Self>, UpstreamUnreachable> {
+        let provider = Http::from_str(url).map_err(|_| UpstreamUnreachable)?;
+        let route = Arc::new(UpstreamRoute {
+            url: url.to_string(),
+            chain_id,
+            provider,
+            policy,
+            calls: Default::default(),
+            failure: Mutex::new(None),
+            failures: Default::default(),
+            leases: 1.into(),
+        });
+
+        let id = Uuid::new_v4();
+        proxy().routes.insert(id, Arc::clone(&route));
+        Ok(Arc::new(UpstreamLease { id, route, failures_seen: Default::default() }))
+    }
+
+    /// Another lease of the same route, for a request on a fork that is shared with others. It
+    /// only reports the failures of the route from now on, and resetting it leaves the other
+    /// leases as they are.
+    pub fn checkout(&self) -> Arc<Self> {
+        self.route.leases.fetch_add(1, Ordering::Relaxed);
+        Arc::new(UpstreamLease {
+            id: self.id,
+            route: Arc::clone(&self.route),
+            failures_seen: AtomicU64::new(self.route.failures.load(Ordering::Relaxed)),
+        })
+    }
+
+    /// URL of the proxy for this fork, to be used as the fork URL.
+    pub fn proxy_url(&self) -> String {
+        format!("http://{}/{}", proxy().addr, self.id)
+    }
+
+    pub fn upstream_url(&self) -> &str {
+        &self.route.url
+    }
+
+    /// Whether requests of this fork are recorded to or replayed from a cassette.
+    pub fn uses_cassette(&self) -> bool {
+        self.route.policy.cassette.is_some()
+    }
+
+    /// Why the proxy last gave up on a request of this fork since the lease was taken or reset, if
+    /// it did.
+    pub fn failure(&self) -> Option<UpstreamFailure> {
+        let failure = self.route.failure.lock().unwrap();
+        if self.route.failures.load(Ordering::Relaxed) > self.failures_seen.load(Ordering::Relaxed) {
+            *failure
+        } else {
+            None
+        }
+    }
+
+    /// Starts a new budget of upstream calls and forgets earlier failures.
+    pub fn reset(&self) {
+        self.route.calls.store(0, Ordering::Relaxed);
+        // Failures are counted under the lock, none is missed or seen twice
+        let _failure = self.route.failure.lock().unwrap();
+        self.failures_seen.store(self.route.failures.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+impl Drop for UpstreamLease {
+    fn drop(&mut self) {
+        if self.route.leases.fetch_sub(1, Ordering::Relaxed) > 1 {
+            return;
+        }
+        if let Some(proxy) = *PROXY.lock().unwrap() {
+            proxy.routes.remove(&self.id);
+        }
+        if let Some(cassette) = &self.route.policy.cassette {
+            if let Err(err) = cassette.save() {
+                log::warn!(target: "ts::api", "Failed to write RPC cassette: {err}");
+            }
+        }
+    }
+}
+
+fn proxy() -> &'static UpstreamProxy {
+    let mut proxy = PROXY.lock().unwrap();
+    *proxy.get_or_insert_with(|| Box::leak(Box::new(start_proxy())))
+}
+
+/// Starts the local JSON-RPC proxy that forks fetch their data through. It retries rate limited
+/// and timed out requests with exponential backoff, spaces out the requests to each upstream and
+/// counts the requests of each fork against its budget. With a cassette, requests are recorded to
+/// it or answered from it instead of the upstream. It runs on its own runtime, so that
+/// blocking EVM work on the server's runtime can't stall it.
+fn start_proxy() -> UpstreamProxy {
+    let routes: Arc<DashMap<Uuid, Arc<UpstreamRoute>>> = Default::default();
+    let next_request_at: Arc<DashMap<String, Instant>> = Default::default();
+
+    let filter_routes = Arc::clone(&routes);
+    let filter_next_request_at = Arc::clone(&next_request_at);
+    let filter = warp::path!(Uuid)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(move |id: Uuid, payload: JsonRpcPayload| {
+            let route = filter_routes.get(&id).map(|route| Arc::clone(route.value()));
+            let next_request_at = Arc::clone(&filter_next_request_at);
+            async move {
+                let reply = match route {
+                    Some(route) => forward_payload(&route, &next_request_at, payload).await,
+                    None => {
+                        let error = JsonRpcError {
+                            code: UPSTREAM_ERROR,
+                            message: "unknown fork".to_string(),
+                            data: None,
+                        };
+                        to_value(response(Value::Null, None, Some(error)))
+                    }
+                };
+                Ok::<_, Infallible>(warp::reply::json(&reply))
+            }
+        });
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder
+            ::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("upstream-proxy")
+            .enable_all()
+            .build()
+            .expect("failed to start the upstream proxy runtime");
+        runtime.block_on(async move {
+            let (addr, server) = warp::serve(filter).bind_ephemeral(([127, 0, 0, 1], 0));
+            sender.send(addr).expect("failed to report the upstream proxy address");
+            server.await;
+        });
+    });
+    let addr = receiver.recv().expect("failed to start the upstream proxy");
+    log::info!(target: "ts::api", "Upstream proxy listening on {addr}");
+
+    UpstreamProxy { addr, routes, next_request_at }
+}
+
+async fn forward_payload(
+    route: &UpstreamRoute,
+    next_request_at: &DashMap<String, Instant>,
+    payload: JsonRpcPayload
+) -> Value {
+    match payload {
+        JsonRpcPayload::Single(request) => to_value(forward(route, next_request_at, request).await),
+        JsonRpcPayload::Batch(requests) => {
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                responses.push(forward(route, next_request_at, request).await);
+            }
+            to_value(responses)
+        }
+    }
+}
+
+async fn forward(
+    route: &UpstreamRoute,
+    next_request_at: &DashMap<String, Instant>,
+    request: JsonRpcRequest
+) -> JsonRpcResponse {
+    let policy = &route.policy;
+    let calls = route.calls.fetch_add(1, Ordering::Relaxed);
+    if policy.budget.map_or(false, |budget| calls >= budget) {
+        return give_up(route, request.id, UpstreamFailure::BudgetExceeded);
+    }
+
+    let cassette = policy.cassette.as_deref();
+    if let Some(cassette) = cassette.filter(|cassette| cassette.mode == CassetteMode::Replay) {
+        return match cassette.replay(route.chain_id, &request.method, &request.params) {
+            Some((result, error)) => response(request.id, result, error),
+            None => {
+                log::warn!(target: "ts::api", "Upstream request {} is not in the RPC cassette", request.method);
+                give_up(route, request.id, UpstreamFailure::NotRecorded)
+            }
+        };
+    }
+    let record = |result: &Option<Value>, error: &Option<JsonRpcError>| {
+        if let Some(cassette) = cassette {
+            cassette.record(route.chain_id, &request.method, &request.params, result.clone(), error.clone());
+        }
+    };
+
+    let mut failure = UpstreamFailure::Timeout;
+    for attempt in 0..=policy.retries {
+        if attempt > 0 {
+            tokio::time::sleep(policy.backoff * 2u32.saturating_pow(attempt - 1)).await;
+        }
+        if let Some(rate_limit) = policy.rate_limit {
+            wait_for_turn(next_request_at, &route.url, rate_limit).await;
+        }
+
+        let result = tokio::time::timeout(
+            policy.timeout,
+            route.provider.request::<_, Value>(&request.method, &request.params)
+        ).await;
+        let err = match result {
+            Ok(Ok(result)) => {
+                let result = Some(result);
+                record(&result, &None);
+                return response(request.id, result, None);
+            }
+            Ok(Err(err)) => err,
+            Err(_) => {
+                failure = UpstreamFailure::Timeout;
+                continue;
+            }
+        };
+
+        if is_rate_limited(&err) {
+            failure = UpstreamFailure::RateLimited;
+        } else if matches!(&err, HttpClientError::ReqwestError(err) if err.is_timeout()) {
+            failure = UpstreamFailure::Timeout;
+        } else if let HttpClientError::JsonRpcError(err) = err {
+            // Errors of the request itself, like reverts or unknown blocks, are passed on as is
+            let error = Some(JsonRpcError { code: err.code, message: err.message, data: err.data });
+            record(&None, &error);
+            return response(request.id, None, error);
+        } else {
+            // Refused connections and responses that are not JSON-RPC, such as error pages
+            log::warn!(target: "ts::api", "Upstream request {} failed: {err}", request.method);
+            return give_up(route, request.id, UpstreamFailure::Unreachable);
+        }
+    }
+
+    log::warn!(target: "ts::api", "Upstream request {} failed: {failure:?}", request.method);
+    give_up(route, request.id, failure)
+}
+
+/// Spaces out the requests to an upstream to at most `rate_limit` per second.
+async fn wait_for_turn(next_request_at: &DashMap<String, Instant>, url: &str, rate_limit: u32) {
+    let now = Instant::now();
+    let interval = Duration::from_secs(1) / rate_limit.max(1);
+    let turn = {
+        let mut next = next_request_at.entry(url.to_string()).or_insert(now);
+        let turn = (*next).max(now);
+        *next = turn + interval;
+        turn
+    };
+    tokio::time::sleep_until(turn.into()).await;
+}
+
+/// Rate limit errors as recognized by ethers' retry policy, except for `header not found`, which
+/// upstreams also answer for blocks they don't have.
+fn is_rate_limited(err: &HttpClientError) -> bool {
+    match err {
+        HttpClientError::JsonRpcError(err) => {
+            err.code == 429 || err.code == -32005 || (err.code == -32016 && err.message.contains("rate limit"))
+        }
+        // Plain HTTP 429 responses have no JSON-RPC body
+        HttpClientError::SerdeJson { text, .. } => {
+            let text = text.to_lowercase();
+            text.contains("429") || text.contains("too many requests") || text.contains("rate limit")
+        }
+        _ => false,
+    }
+}
+
+fn give_up(route: &UpstreamRoute, id: Value, failure: UpstreamFailure) -> JsonRpcResponse {
+    {
+        let mut last_failure = route.failure.lock().unwrap();
+        *last_failure = Some(failure);
+        route.failures.fetch_add(1, Ordering::Relaxed);
+    }
+    let message = match failure {
+        UpstreamFailure::RateLimited => "UPSTREAM_RATE_LIMITED",
+        UpstreamFailure::Timeout => "UPSTREAM_TIMEOUT",
+        UpstreamFailure::BudgetExceeded => "UPSTREAM_BUDGET_EXCEEDED",
+        UpstreamFailure::Unreachable => "UPSTREAM_UNREACHABLE",
+        UpstreamFailure::NotRecorded => "UPSTREAM_NOT_RECORDED",
+    };
+    response(id, None, Some(JsonRpcError { code: UPSTREAM_ERROR, message: message.to_string(), data: None }))
+}
+
+fn response(id: Value, result: Option<Value>, error: Option<JsonRpcError>) -> JsonRpcResponse {
+    JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result,
+        error,
+    }
+}
+
+fn to_value<T: serde::Serialize>(value: T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
@@ -0,0 +1,92 @@
+use std::future::Future;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::Arc;
+
+use tokio::runtime::Handle;
+use tokio::sync::{ Mutex, OwnedMutexGuard, Semaphore };
+use warp::Rejection;
+
+use crate::structs::{ Evm, SimulationQueueFull, SimulationWorkers };
+
+impl SimulationWorkers {
+    /// `workers` EVMs run at once, at most `queue_size` requests wait for their turn. The queue is
+    /// not limited without a size.
+    pub fn new(workers: usize, queue_size: Option<usize>) -> Self {
+        let workers = workers.max(1);
+        SimulationWorkers {
+            workers,
+            queue_size,
+            permits: Arc::new(Semaphore::new(workers)),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Runs `job` on a worker once one is free. EVMs fetch fork data with blocking calls, so they
+    /// must not run on the server's runtime threads, where they would hold up all other requests.
+    pub fn run<T, Fut>(self: &Arc<Self>, job: Fut) -> impl Future<Output = Result<T, Rejection>> + Send
+        where Fut: Future<Output = Result<T, Rejection>> + Send + 'static, T: Send + 'static
+    {
+        let workers = Arc::clone(self);
+        async move {
+            let permit = match Arc::clone(&workers.permits).try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    let _slot = QueueSlot::take(&workers.queued, workers.queue_size)?;
+                    Arc::clone(&workers.permits)
+                        .acquire_owned().await
+                        .expect("the simulation worker semaphore is never closed")
+                }
+            };
+
+            let runtime = Handle::current();
+            let result = tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                runtime.block_on(job)
+            }).await;
+            match result {
+                Ok(result) => result,
+                Err(err) => std::panic::resume_unwind(err.into_panic()),
+            }
+        }
+    }
+
+    /// Runs `job` with the EVM of a stateful simulation on a worker. The simulation is locked
+    /// before waiting for a worker, so that requests queued on a busy simulation don't hold
+    /// workers that other requests could run on.
+    pub fn run_in_session<T, F, Fut>(
+        self: &Arc<Self>,
+        session: Result<Arc<Mutex<Evm>>, Rejection>,
+        job: F
+    ) -> impl Future<Output = Result<T, Rejection>> + Send
+        where
+            F: FnOnce(OwnedMutexGuard<Evm>) -> Fut + Send + 'static,
+            Fut: Future<Output = Result<T, Rejection>> + Send + 'static,
+            T: Send + 'static
+    {
+        let workers = Arc::clone(self);
+        async move {
+            let evm = session?.lock_owned().await;
+            workers.run(job(evm)).await
+        }
+    }
+}
+
+/// Place of a request in the queue, given up when the request gets a worker or is dropped.
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl<'a> QueueSlot<'a> {
+    fn take(queued: &'a AtomicUsize, queue_size: Option<usize>) -> Result<Self, SimulationQueueFull> {
+        let position = queued.fetch_add(1, Ordering::Relaxed);
+        let slot = QueueSlot(queued);
+        if queue_size.map_or(false, |size| position >= size) {
+            return Err(SimulationQueueFull);
+        }
+        Ok(slot)
+    }
+}
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
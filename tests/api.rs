@@ -1,31 +1,60 @@
-use std::{fs::File, sync::Arc, io::Write};
+use std::{
+    convert::Infallible,
+    fs::File,
+    io::Write,
+    sync::{atomic::{AtomicUsize, Ordering}, Arc},
+    time::Duration,
+};
 
-use dashmap::DashMap;
-use ethers::types::U256;
+use ethers::{
+    signers::{LocalWallet, Signer},
+    types::{transaction::eip2718::TypedTransaction, TransactionRequest, U256},
+    utils::keccak256,
+};
 use symunix::{
     config::config,
     errors::handle_rejection,
     simulate_routes,
+    with_api_key,
     structs::{
         SimulationRequest, SimulationResponse, StatefulSimulationEndResponse,
-        StatefulSimulationResponse, ErrorMessage, Config
+        StatefulSimulationResponse, ErrorMessage, Config, CallBundleResponse,
+        StatefulSimulationSnapshotResponse, StatefulSimulationInfoResponse, SerializableState,
+        StatefulSimulationAccountResponse, StatefulSimulationStorageResponse, JsonRpcResponse,
+        ChainResponse, SimulationWorkers, PersistedSession, MultiChainSimulationResponse,
+        ChainRegistry, OfflineState, Cassette, CassetteMode,
     },
     SharedSimulationState,
 };
-use warp::Filter;
+use warp::{http::StatusCode, Filter, Reply};
 
 fn filter(
     config: Config,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = std::convert::Infallible> + Clone {
-    let shared_state: Arc<SharedSimulationState> = Arc::new(SharedSimulationState {
-        evms: Arc::new(DashMap::new()),
-    });
+    let shared_state: Arc<SharedSimulationState> = Arc::new(SharedSimulationState::default());
 
     warp::any()
         .and(simulate_routes(config, shared_state))
         .recover(handle_rejection)
 }
 
+/// Config that replays the upstream requests of a test from `tests/cassette.json`, so that it runs
+/// against the same data every time and without network access. With `RPC_CASSETTE_MODE=record`,
+/// they are sent to the upstreams and added to the cassette instead.
+fn replayed_config() -> Config {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cassette.json");
+    let mode = match std::env::var("RPC_CASSETTE_MODE").as_deref() {
+        Ok("record") => CassetteMode::Record,
+        _ => CassetteMode::Replay,
+    };
+    if mode == CassetteMode::Replay && !std::path::Path::new(path).exists() {
+        panic!("{path} is missing, record it with `RPC_CASSETTE_MODE=record cargo test`");
+    }
+    let cassette = Cassette::load(path, mode).unwrap();
+
+    Config { rpc_cassette: Some(Arc::new(cassette)), ..config() }
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_file() {
     if config().etherscan_key.is_some() {
@@ -33,7 +62,7 @@ async fn post_simulate_file() {
         return;
     }
 
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let file = File::open("tests/body.json").expect("file should open read only");
     let json: SimulationRequest =
@@ -71,7 +100,7 @@ async fn post_simulate_file_etherscan() {
         return;
     }
 
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let file = File::open("tests/body.json").expect("file should open read only");
     let json: SimulationRequest =
@@ -101,7 +130,7 @@ async fn post_simulate_file_etherscan() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_frax_tx() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!({
       "chainId": 1,
@@ -129,7 +158,7 @@ async fn post_simulate_frax_tx() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_zerox_swap() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!({
       "chainId": 1,
@@ -158,7 +187,7 @@ async fn post_simulate_zerox_swap() {
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_access_lists() {
     let simulate_gas_used = |access_list: serde_json::Value| async move {
-        let filter = filter(config());
+        let filter = filter(replayed_config());
 
         let json = serde_json::json!({
           "chainId": 1,
@@ -209,7 +238,7 @@ async fn post_simulate_access_lists() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_state_overrides() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!({
       "chainId": 1,
@@ -244,7 +273,7 @@ async fn post_simulate_state_overrides() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_bundle_single_zerox_swap() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!([{
       "chainId": 1,
@@ -273,7 +302,7 @@ async fn post_simulate_bundle_single_zerox_swap() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_bundle() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!([{
       "chainId": 1,
@@ -309,7 +338,7 @@ async fn post_simulate_bundle() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_bundle_second_reverts() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!([{
       "chainId": 1,
@@ -343,9 +372,158 @@ async fn post_simulate_bundle_second_reverts() {
     assert!(!body[1].success);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_revert_policy() {
+    let filter = filter(replayed_config());
+
+    let bundle = |revert_policy: &str, can_revert: bool| {
+        serde_json::json!({
+          "revertPolicy": revert_policy,
+          "transactions": [{
+            "chainId": 1,
+            "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+            "to": "0x60f727bdead2ce49b00f2a2133fc707b931d130b",
+            "gasLimit": 5000000,
+            "blockNumber": 16968595,
+            "canRevert": can_revert,
+          }, {
+            "chainId": 1,
+            "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+            "to": "0xdac17f958d2ee523a2206206994597c13d831ec7",
+            "gasLimit": 5000000,
+            "blockNumber": 16968595,
+          }]
+        })
+    };
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&bundle("stopOnFirstRevert", false))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.len(), 1);
+    assert!(!body[0].success);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&bundle("atomic", true))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.len(), 2);
+    assert!(!body[0].success);
+    assert!(body[1].success);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_bundle_empty() {
+    let filter = filter(replayed_config());
+
+    for json in [serde_json::json!([]), serde_json::json!({ "revertPolicy": "atomic", "transactions": [] })] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/simulate-bundle")
+            .json(&json)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 400);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_call_bundle() {
+    let filter = filter(replayed_config());
+
+    let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        .parse::<LocalWallet>()
+        .unwrap()
+        .with_chain_id(1u64);
+    let tx: TypedTransaction = TransactionRequest::new()
+        .to("0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5".parse::<ethers::types::Address>().unwrap())
+        .value(0)
+        .gas(21000)
+        .gas_price(U256::from(100_000_000_000u64))
+        .nonce(0)
+        .chain_id(1)
+        .into();
+    let signature = wallet.sign_transaction_sync(&tx).unwrap();
+    let raw = tx.rlp_signed(&signature);
+
+    let json = serde_json::json!({
+      "txs": [raw],
+      "blockNumber": "0x1001d59",
+      "stateBlockNumber": "0x1001d58",
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/call-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: CallBundleResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.results.len(), 1);
+    assert_eq!(body.total_gas_used, 21000);
+    assert_eq!(body.state_block_number, 16784728);
+    assert_eq!(body.results[0].from_address, wallet.address());
+    assert_eq!(body.results[0].eth_sent_to_coinbase, "0");
+    assert_eq!(body.bundle_hash.0, keccak256(keccak256(&raw)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_call_bundle_contract_creation() {
+    let filter = filter(replayed_config());
+
+    let wallet: LocalWallet = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        .parse::<LocalWallet>()
+        .unwrap()
+        .with_chain_id(1u64);
+    let tx: TypedTransaction = TransactionRequest::new()
+        .data(vec![0x60, 0x00, 0x60, 0x00, 0xf3])
+        .gas(100000)
+        .gas_price(U256::from(100_000_000_000u64))
+        .nonce(0)
+        .chain_id(1)
+        .into();
+    let signature = wallet.sign_transaction_sync(&tx).unwrap();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/call-bundle")
+        .json(&serde_json::json!({
+          "txs": [tx.rlp_signed(&signature)],
+          "blockNumber": "0x1001d59",
+          "stateBlockNumber": "0x1001d58",
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "CONTRACT_CREATION_NOT_SUPPORTED".to_string());
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_no_data() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!({
       "chainId": 1,
@@ -372,8 +550,8 @@ async fn post_simulate_no_data() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_incorrect_chain_id() {
-    temp_env::async_with_vars([("FORK_URL", Some("https://eth.llamarpc.com"))], async {
-        let filter = filter(config());
+    temp_env::async_with_vars([("RPC_URLS_137", Some("https://eth.llamarpc.com"))], async {
+        let filter = filter(replayed_config());
 
         let json = serde_json::json!({
           "chainId": 137,
@@ -391,18 +569,230 @@ async fn post_simulate_incorrect_chain_id() {
             .reply(&filter)
             .await;
 
-        assert_eq!(res.status(), 400);
+        assert_eq!(res.status(), 502);
 
         let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
 
-        assert_eq!(body.message, "INCORRECT_CHAIN_ID".to_string());
+        assert_eq!(body.message, "UPSTREAM_CHAIN_MISMATCH".to_string());
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_block_not_found() {
+    let filter = filter(replayed_config());
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 999999999
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "BLOCK_NOT_FOUND".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a live upstream to fail over to"]
+async fn post_simulate_upstream_failover() {
+    let rpc_urls = "http://127.0.0.1:1,https://eth.llamarpc.com";
+    temp_env::async_with_vars([("RPC_URLS_1", Some(rpc_urls))], async {
+        let filter = filter(config());
+
+        let json = serde_json::json!({
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+          "gasLimit": 21000,
+          "value": "100000",
+          "blockNumber": 16784600
+        });
+
+        for _ in 0..2 {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/simulate")
+                .json(&json)
+                .reply(&filter)
+                .await;
+
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers()["X-Upstream"], "eth.llamarpc.com");
+        }
     })
     .await;
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_rpc_budget_exceeded() {
+    let filter = filter(Config { simulation_rpc_budget: Some(1), ..replayed_config() });
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "UPSTREAM_BUDGET_EXCEEDED".to_string());
+}
+
+/// Starts a mainnet upstream that answers `eth_getBlockByNumber` with HTTP 429, or never answers
+/// it if `hang` is set. Returns its URL and the number of `eth_getBlockByNumber` requests.
+fn mock_upstream(hang: bool) -> (String, Arc<AtomicUsize>) {
+    let block_requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&block_requests);
+    let filter = warp::post()
+        .and(warp::body::json())
+        .and_then(move |request: serde_json::Value| {
+            let counter = Arc::clone(&counter);
+            async move {
+                let result = match request["method"].as_str() {
+                    Some("eth_chainId") => "0x1",
+                    Some("eth_gasPrice") => "0x1",
+                    Some("eth_blockNumber") => "0x1001cd8",
+                    _ => {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        if hang {
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                        }
+                        let reply = warp::reply::with_status("Too Many Requests", StatusCode::TOO_MANY_REQUESTS);
+                        return Ok::<_, Infallible>(reply.into_response());
+                    }
+                };
+                let reply = serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+                Ok(warp::reply::json(&reply).into_response())
+            }
+        });
+    let (addr, server) = warp::serve(filter).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    (format!("http://{addr}"), block_requests)
+}
+
+fn mock_upstream_config(rpc_url: String) -> Config {
+    let mut chains = ChainRegistry::builtin();
+    chains.set_rpc_urls(1, vec![rpc_url]);
+
+    Config {
+        chains: Arc::new(chains),
+        upstream_retries: 2,
+        upstream_backoff: 10,
+        upstream_timeout: 1,
+        // Requests must reach the mock, not be answered from a cassette
+        rpc_cassette: None,
+        ..config()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_upstream_rate_limited() {
+    let (rpc_url, block_requests) = mock_upstream(false);
+    let filter = filter(mock_upstream_config(rpc_url));
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 503);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "UPSTREAM_RATE_LIMITED".to_string());
+    // The first request and its 2 retries
+    assert_eq!(block_requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_upstream_timeout() {
+    let (rpc_url, block_requests) = mock_upstream(true);
+    let filter = filter(mock_upstream_config(rpc_url));
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 504);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "UPSTREAM_TIMEOUT".to_string());
+    assert_eq!(block_requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_chains() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/chains")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<ChainResponse> = serde_json::from_slice(res.body()).unwrap();
+    let polygon = body.iter().find(|chain| chain.chain_id == 137).unwrap();
+
+    assert_eq!(polygon.native_currency.symbol, "MATIC");
+    assert_eq!(polygon.block_time, 2);
+    assert!(!String::from_utf8_lossy(res.body()).contains("rpcUrls"));
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_not_enough_gas() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!({
       "chainId": 1,
@@ -429,7 +819,7 @@ async fn post_simulate_not_enough_gas() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn post_simulate_bundle_multiple_block_numbers() {
-    let filter = filter(config());
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!([{
       "chainId": 1,
@@ -496,23 +886,31 @@ async fn post_simulate_bundle_multiple_block_numbers() {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn post_simulate_bundle_multiple_block_numbers_invalid_order() {
-    let filter = filter(config());
+async fn post_simulate_bundle_advance_blocks() {
+    let filter = filter(replayed_config());
 
     let json = serde_json::json!([{
       "chainId": 1,
       "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
-      "to": "0xdac17f958d2ee523a2206206994597c13d831ec7",
-      "data": "0x095ea7b300000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000989680",
+      "to": "0x7E7d64D987cAb6EeD08A191C4C2459dAF2f8ED0B",
       "gasLimit": 5000000,
       "blockNumber": 16968597,
-    }, {
+    },
+    {
       "chainId": 1,
       "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
-      "to": "0x60f727bdead2ce49b00f2a2133fc707b931d130b",
-      "data": "0x8fd8d1bbffc9011b73f477fef5d4ebdd0903025283464275113654045eef0ce1ba22ccea000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001400000000000000000000000000000000000000000000000000000000000000006f8b2cb4f0100ffffffffff057e7d64d987cab6eed08a191c4c2459daf2f8ed0b095ea7b3010102ffffffffffdac17f958d2ee523a2206206994597c13d831ec719198595a183ffffffffffffdef1c0ded9bec7f1a1670819833240f027b25efff8b2cb4f0100ffffffffff007e7d64d987cab6eed08a191c4c2459daf2f8ed0bb67d77c5010005ffffffff00742f2c5d96c0858d00860039c22d2805bed420e8a1903eab030004ffffffffffae7ab96520de3a18e5e111b5eaab095312d7fe84000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000c000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000140000000000000000000000000000000000000000000000000000000000000018000000000000000000000000000000000000000000000000000000000000002e00000000000000000000000000000000000000000000000000000000000000320000000000000000000000000000000000000000000000000000000000000002000000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000000020000000000000000000000000def1c0ded9bec7f1a1670819833240f027b25eff000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000009896800000000000000000000000000000000000000000000000000000000000000128d9627aa40000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000098968000000000000000000000000000000000000000000000000000130e53e715e8a500000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000000000000000000000000dac17f958d2ee523a2206206994597c13d831ec7000000000000000000000000eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee869584cd00000000000000000000000010000000000000000000000000000000000000110000000000000000000000000000000000000000000000cef2d4383a642acb0d000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+      "to": "0x7E7d64D987cAb6EeD08A191C4C2459dAF2f8ED0B",
       "gasLimit": 5000000,
-      "blockNumber": 16968596,
+      "advanceBlocks": 2,
+      "advanceTime": 5,
+    },
+    {
+      "chainId": 1,
+      "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+      "to": "0x7E7d64D987cAb6EeD08A191C4C2459dAF2f8ED0B",
+      "gasLimit": 5000000,
+      "blockNumber": 16968600,
+      "blockTimestamp": 1680526200,
     }]);
 
     let res = warp::test::request()
@@ -522,27 +920,72 @@ async fn post_simulate_bundle_multiple_block_numbers_invalid_order() {
         .reply(&filter)
         .await;
 
-    assert_eq!(res.status(), 400);
+    assert_eq!(res.status(), 200);
 
-    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
 
-    assert_eq!(body.message, "INVALID_BLOCK_NUMBERS".to_string());
+    assert_eq!(body.len(), 3);
+    assert_eq!(body[1].block_number, 16968599);
+    assert_eq!(body[2].block_number, 16968600);
+
+    assert_eq!(
+        U256::from(body[1].return_data.0.to_vec().as_slice()),
+        U256::from(1680526127 + 2 * 12 + 5)
+    );
+    assert_eq!(
+        U256::from(body[2].return_data.0.to_vec().as_slice()),
+        U256::from(1680526200)
+    );
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn post_simulate_stateful() {
-    let filter = filter(config());
-
-    let new_simulation_req = serde_json::json!({
-        "chainId": 1,
-        "gasLimit": 5000000,
-        "blockNumber": 16968594,
-    });
+async fn post_simulate_bundle_multiple_block_numbers_invalid_order() {
+    let filter = filter(replayed_config());
 
-    let res = warp::test::request()
-        .method("POST")
-        .path("/simulate-stateful")
-        .json(&new_simulation_req)
+    let json = serde_json::json!([{
+      "chainId": 1,
+      "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+      "to": "0xdac17f958d2ee523a2206206994597c13d831ec7",
+      "data": "0x095ea7b300000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000989680",
+      "gasLimit": 5000000,
+      "blockNumber": 16968597,
+    }, {
+      "chainId": 1,
+      "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+      "to": "0x60f727bdead2ce49b00f2a2133fc707b931d130b",
+      "data": "0x8fd8d1bbffc9011b73f477fef5d4ebdd0903025283464275113654045eef0ce1ba22ccea000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001400000000000000000000000000000000000000000000000000000000000000006f8b2cb4f0100ffffffffff057e7d64d987cab6eed08a191c4c2459daf2f8ed0b095ea7b3010102ffffffffffdac17f958d2ee523a2206206994597c13d831ec719198595a183ffffffffffffdef1c0ded9bec7f1a1670819833240f027b25efff8b2cb4f0100ffffffffff007e7d64d987cab6eed08a191c4c2459daf2f8ed0bb67d77c5010005ffffffff00742f2c5d96c0858d00860039c22d2805bed420e8a1903eab030004ffffffffffae7ab96520de3a18e5e111b5eaab095312d7fe84000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000c000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000140000000000000000000000000000000000000000000000000000000000000018000000000000000000000000000000000000000000000000000000000000002e00000000000000000000000000000000000000000000000000000000000000320000000000000000000000000000000000000000000000000000000000000002000000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000000020000000000000000000000000def1c0ded9bec7f1a1670819833240f027b25eff000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000009896800000000000000000000000000000000000000000000000000000000000000128d9627aa40000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000098968000000000000000000000000000000000000000000000000000130e53e715e8a500000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000000000000000000000000dac17f958d2ee523a2206206994597c13d831ec7000000000000000000000000eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee869584cd00000000000000000000000010000000000000000000000000000000000000110000000000000000000000000000000000000000000000cef2d4383a642acb0d000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+      "gasLimit": 5000000,
+      "blockNumber": 16968596,
+    }]);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-bundle")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "INVALID_BLOCK_NUMBERS".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful() {
+    let filter = filter(replayed_config());
+
+    let new_simulation_req = serde_json::json!({
+        "chainId": 1,
+        "gasLimit": 5000000,
+        "blockNumber": 16968594,
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&new_simulation_req)
         .reply(&filter)
         .await;
 
@@ -671,54 +1114,1911 @@ async fn post_simulate_stateful() {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn post_simulate_time_sensitive_tx() {
-    let config = Config {
-        max_request_size: 64 * 1024,
-        ..config()
+async fn post_simulate_stateful_atomic_bundle() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let recipient = "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5";
+    let balance = |res: warp::http::Response<bytes::Bytes>| {
+        assert_eq!(res.status(), 200);
+        serde_json::from_slice::<StatefulSimulationAccountResponse>(res.body()).unwrap().balance
     };
-    let filter = filter(config);
 
-    let mut json = serde_json::json!({
-      "chainId": 1,
-      "from": "0x16c473448e770ff647c69cbe19e28528877fba1b",
-      "to": "0x9008d19f58aabd9ed0d60971565aa8510560ab41",
-      "data": "0x13d79a0b000000000000000000000000000000000000000000000000000000000000008000000000000000000000000000000000000000000000000000000000000001c000000000000000000000000000000000000000000000000000000000000003000000000000000000000000000000000000000000000000000000000000000f80000000000000000000000000000000000000000000000000000000000000000900000000000000000000000063b420fb3294ba1d300ce5d3ba4bbca0f4fe5e3b0000000000000000000000006602e9319f2c5ec0ba31ffcdc4301d7ef03b709e000000000000000000000000826180541412d574cf1336d22c0c0a287822678a000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f000000000000000000000000eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee000000000000000000000000826180541412d574cf1336d22c0c0a287822678a000000000000000000000000d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f0000000000000000000000000000000000000000000000000000000000000009000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000b03be4f33de3117a20000000000000000000000000000000000000000000001dbdaf552b2e610f9620000000000000000000000000000000000004aaa0d413df15c88a41958d3500500000000000000000000000000000000000000000002d6d05933e23134fa4940000000000000000000000000000000000000000000000001a70d5fdd2071474f00000000000000000000000000000000000000000002d6d05933e23134fa4940000000000000000000000000000000000000000000003c97e632500fe652a7f400000000000000000000000000000000000000000000003635c9adc5dea0000000000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000000026000000000000000000000000000000000000000000000000000000000000008c00000000000000000000000000000000000000000000000000000000000000a8000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000d0dfd95ccd6e070eda7e153a54fb67f0628ba26c00000000000000000000000000000000000000000000281af54148361a9400000000000000000000000000000000000000000000000000000c20964c235c076b00000000000000000000000000000000000000000000000000000000657c0368e9f29ae547955463ed535162aefee525d8d309571a2b18bc26086c8c35d781eb000000000000000000000000000000000000000000000020f66db0a5202c0000000000000000000000000000000000000000000000000000000000000000000800000000000000000000000000000000000000000000281af54148361a9400000000000000000000000000000000000000000000000000000000000000000160000000000000000000000000000000000000000000000000000000000000004176b5974825abcbf26f0e2312e950444ca46758a8e41df942369d5b1395c630b40e1b1e728d4589f16fff33287cfae3922c81e004bef6207d1a733f51a8da45741c00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000700000000000000000000000000000000000000000000000000000000000000080000000000000000000000006aeef86bb8742c6739639634f64ec8d98d6b835800000000000000000000000000000000000000000000003635c9adc5dea00000000000000000000000000000000000000000000000003af311dd167044bb7b3800000000000000000000000000000000000000000000000000000000657bf182351173d8df3ae67ecd9202256c01a4ccbaf71234af377033ba8e9c53af6835460000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004000000000000000000000000000000000000000000000003635c9adc5dea00000000000000000000000000000000000000000000000000000000000000000016000000000000000000000000000000000000000000000000000000000000004d86aeef86bb8742c6739639634f64ec8d98d6b83585fd7e97dc078f884a2676e1345748b1feace7b0abee5d00ecadb6e574dcdd109a63e8943d5a25ba2e97094ad7d83dc28a6572da797d6b3e7fc6663bd93efb789fc17e489000000000000000000000000000000000000000000000000000000000000008000000000000000000000000000000000000000000000000000000000000002200000000000000000000000000000000000000000000000000000000000000180000000000000000000000000826180541412d574cf1336d22c0c0a287822678a000000000000000000000000d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f0000000000000000000000006aeef86bb8742c6739639634f64ec8d98d6b835800000000000000000000000000000000000000000000003635c9adc5dea00000000000000000000000000000000000000000000000003af311dd167044bb7b3800000000000000000000000000000000000000000000000000000000657bf182351173d8df3ae67ecd9202256c01a4ccbaf71234af377033ba8e9c53af6835460000000000000000000000000000000000000000000000000000000000000000f3b277728b3fee749481eb3e0b3b48980dbbab78658fc419025cb16eee34677500000000000000000000000000000000000000000000000000000000000000005a28e9363bb942b639270062aa6bb295f434bcdfc42c97267bf003f272060dc95a28e9363bb942b639270062aa6bb295f434bcdfc42c97267bf003f272060dc90000000000000000000000000000000000000000000000000000000000000280000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000000024000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006cf1e9ca41f7611def408122793c358a3d11e5a500000000000000000000000000000000000000000000000000000018c6c0264000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000140000000000000000000000000826180541412d574cf1336d22c0c0a287822678a000000000000000000000000d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f0000000000000000000000006aeef86bb8742c6739639634f64ec8d98d6b835800000000000000000000000000000000000000000000003635c9adc5dea00000000000000000000000000000000000000000000000003af311dd167044bb7b380000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000000000000000000000000000000000004b00000000000000000000000000000000000000000000000000000000000000000351173d8df3ae67ecd9202256c01a4ccbaf71234af377033ba8e9c53af68354600000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000400000000000000000000000000000000000000000000000000000000000000030000000000000000000000002f45724d7e384b38d5c97206e78470544304887f000000000000000000000000000000000000000000000000989cd478134caef300000000000000000000000000000000000000000000000000000005b06a600d00000000000000000000000000000000000000000000000000000000ffffffff15f7794a143d0e5d3217190e3baeac63e6f76c816b10b9a6b89fcb915164d281000000000000000000000000000000000000000000000000000b05401dff510d0000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000989cd478134caef30000000000000000000000000000000000000000000000000000000000000160000000000000000000000000000000000000000000000000000000000000002840a50cf069e992aa4536211b23f286ef8875218740a50cf069e992aa4536211b23f286ef88752187000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000030000000000000000000000009afd9f0c276b40411a4ca6a53dfa7e3d832cd66f0000000000000000000000000000000000000000000008f63ce03c890736f2a0000000000000000000000000000000000000000000000000000000013e4ab2da00000000000000000000000000000000000000000000000000000000657bf3f8db47033948acea8674bb2501bc6d2908d3cdf86eb9262a8585466e877e4ecd89000000000000000000000000000000000000000000000002ca36c150997d600000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000008f63ce03c890736f2a000000000000000000000000000000000000000000000000000000000000001600000000000000000000000000000000000000000000000000000000000000041da7fefd12133c5dd85ac4ca604a517de4dcf526d7fb9e363ad8eb4c9b4f2e57d0e5b72ca03e2e2ec68d7486060c42802e01d42b7366fc64c6bdbd751cae6624e1b000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000014000000000000000000000000000000000000000000000000000000000000022e00000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000002000000000000000000000000040a50cf069e992aa4536211b23f286ef887521870000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000044c84c1c8000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000008a00000000000000000000000000000000000000000000000000000000000000a400000000000000000000000000000000000000000000000000000000000000ca00000000000000000000000000000000000000000000000000000000000000e400000000000000000000000000000000000000000000000000000000000000f60000000000000000000000000000000000000000000000000000000000000102000000000000000000000000000000000000000000000000000000000000014c00000000000000000000000000000000000000000000000000000000000001580000000000000000000000000000000000000000000000000000000000000164000000000000000000000000000000000000000000000000000000000000018a000000000000000000000000000000000000000000000000000000000000019600000000000000000000000000000000000000000000000000000000000001b200000000000000000000000000000000000000000000000000000000000001c800000000000000000000000000000000000000000000000000000000000001ee000000000000000000000000000000000000000000000000000000000000020c0000000000000000000000000beb09000fa59627dc02bb55448ac1893eaa501a500000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000060424a305f20000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000046000000000000000000000000000000000000000000000000000000000000004c000000000000000000000000000000000000000000000000000000000657beebe0000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab410000000000000000000000000000000000000000000000000000000000000140000000000000000000000000000000000000000000000000000000000000018000000000000000000000000000000000000000000000000000000000000001c0000000000000000000000000000000000000000000000000000000000000024000000000000000000000000000000000000000000000000000000000000002c000000000000000000000000000000000000000000000000000000000000003400000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000003c0000000000000000000000000000000000000000000000000000000000000000100000000000000000000000051c72848c68a965f66fa7a88855f9f7784502a7f0000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000003187e072c1b000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000001000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000001000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000989cd478134caef300000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000005cd98afdf0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000004000000000000000000000000000000000000000000000000000000000000000418fba48f1c1f9d272578cfc682e24d59bbbf341683dced64b5088822638e46a344dd1c2aa35d76c624fcd6720eeb1bb79bfe1a2b8e9838f31de532c438ed7d6461b0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e592427a0aece92de3edee1f18e0157c05861564000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000104414bf3890000000000000000000000006602e9319f2c5ec0ba31ffcdc4301d7ef03b709e000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc200000000000000000000000000000000000000000000000000000000000027100000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000f00000000000000000000000000000000000000000000000000000008f63ce03c890736f27a00000000000000000000000000000000000000000000000022b0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ba12222222228d8ba445958a75a0704d566bf2c80000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001c452bbbe2900000000000000000000000000000000000000000000000000000000000000e00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006e0000000000000000000000000000000000000000000000000000000000f0000000000af15e6cbe19e30be827f27069cf010b57ce9f3ae0002000000000000000004d30000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800000000000000000000000000000000000000000000000000b3cea2601d204400000000000000000000000000000000000000000000000000000000000000c00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001b81d678ffb9c0263b24a97847620c99d213eb14000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000104414bf389000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800000000000000000000000000000000000000000000000000000000000001f40000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000f00000000000000000000000000000000000000000000000000000000000c65e538006d384d0000000000000000000000000000000000000000000000000000000078b000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000005426178799ee0a0181a89b4f57efddfab49941ec0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000845b41b90800000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000042daef9a502b9790000000000000000000000000000000000000000000000000000000028b000000000000000000000000000000000000000000000000000000000000000000000000000000000000008775ccb6674d6bdceb0797c364c2653ed84f38400000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000002438e9922e000000000000000000000000000000000000000000000000000221b262dd800000000000000000000000000000000000000000000000000000000000000000000000000000000000ba12222222228d8ba445958a75a0704d566bf2c8000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000404945bcec90000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000012000000000000000000000000000000000000000000000000000000000000003000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab410000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000038000000000000000000000000000000000000000000000000000000f000000000000000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000010008775ccb6674d6bdceb0797c364c2653ed84f3840002000000000000000004f0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000bdf1d19bac30b5800000000000000000000000000000000000000000000000000000000000000a0000000000000000000000000000000000000000000000000000000000000000079c58f70905f734641735bc61e45c19dd9ad60bc0000000000000000000004e700000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000003000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc200000000000000000000000079c58f70905f734641735bc61e45c19dd9ad60bc000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800000000000000000000000000000000000000000000000000000000000000030000000000000000000000000000000000000000000000000bdf1d19bac30b580000000000000000000000000000000000000000000000000000000000000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffff8bf000000000000000000000000000000000000000000000000000000000000000000000000000000000000008775ccb6674d6bdceb0797c364c2653ed84f38400000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000002438e9922e000000000000000000000000000000000000000000000000000aa87bee5380000000000000000000000000000000000000000000000000000000000000000000000000000000000096646936b91d6b9d7d0c47c496afbf3d6ec7b6f800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000002438e9922e000000000000000000000000000000000000000000000000000221b262dd800000000000000000000000000000000000000000000000000000000000000000000000000000000000ba12222222228d8ba445958a75a0704d566bf2c80000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001c452bbbe2900000000000000000000000000000000000000000000000000000000000000e00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab410000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000d70000000000000000000000000000000000000000000000000000000000f000000000096646936b91d6b9d7d0c47c496afbf3d6ec7b6f80002000000000000000000190000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800000000000000000000000000000000000000000000000001618e2c8343630c00000000000000000000000000000000000000000000000000000000000000c000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000096646936b91d6b9d7d0c47c496afbf3d6ec7b6f800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000002438e9922e000000000000000000000000000000000000000000000000000aa87bee53800000000000000000000000000000000000000000000000000000000000000000000000000000000000bbf1ee38152e9d8e3470dc47947eaa65dca94913000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000124c04b8d59000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000f000000000000000000000000000000000000000000000000000000000000b14d25fe99ce1e0000000000000000000000000000000000000000000000000000000006b00000000000000000000000000000000000000000000000000000000000000000003cc02aaa39b223fe8d0a0e5c4f27ead9083c756cc211a653ddfbb61e0feff5484919f06d9d254bf65fa0b86991c6218b36c1d19d4a2e9eb0ce3606eb480000000000000000000000000000000000000000000000000000000000000000000000000000000000000000831bf48183b999fde45294b14b55199072f0801b0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000c475d39ecb0000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000377936629f03f34000000000000000000000000fffd8963efd1fc6a506488495d951d5263988d250000000000000000000000000000000000000000000000000000000021c0000000000000000000000000000000000000000000000000000000000f000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ba12222222228d8ba445958a75a0704d566bf2c80000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001c452bbbe2900000000000000000000000000000000000000000000000000000000000000e00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e2000000000000000000000000000000000000000000000000000000000000000000f000000000040f1cb91325dd8ef24dfac085c9ba46ea309e5e2000200000000000000000644000000000000000000000000000000000000000000000000000000000000000000000000000000000000000063b420fb3294ba1d300ce5d3ba4bbca0f4fe5e3b000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc200000000000000000000000000000000000000000000281af54148361a94000000000000000000000000000000000000000000000000000000000000000000c0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e592427a0aece92de3edee1f18e0157c05861564000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000144c04b8d59000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000f0000000000000000000000000000000000000000000000000000000035dea0e8d91c9800f9000000000000000000000000000000000000000000003c9000000000000000000000000000000000000000000000000000000000000000000000000000000042826180541412d574cf1336d22c0c0a287822678a000bb8c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000bb8d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc20000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000242e1a7d4d0000000000000000000000000000000000000000000000000e204038c7e0f68e00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000007c70ae",
-      "gasLimit": 5000000,
-      "value": "0",
-      "blockNumber": 18789653,
-      // happened at timestamp 1702617167
-    });
+    let balance_before = balance(
+        warp::test::request()
+            .method("GET")
+            .path(format!("/simulate-stateful/{id}/account/{recipient}").as_str())
+            .reply(&filter)
+            .await
+    );
 
     let res = warp::test::request()
         .method("POST")
-        .path("/simulate")
-        .json(&json)
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!({
+          "revertPolicy": "atomic",
+          "transactions": [{
+            "chainId": 1,
+            "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+            "to": recipient,
+            "value": "1000000000000000000",
+            "gasLimit": 5000000,
+          }, {
+            "chainId": 1,
+            "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+            "to": "0x60f727bdead2ce49b00f2a2133fc707b931d130b",
+            "gasLimit": 5000000,
+          }]
+        }))
         .reply(&filter)
         .await;
 
     assert_eq!(res.status(), 200);
 
-    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
 
-    assert!(body.success);
+    assert_eq!(body.len(), 2);
+    assert!(body[0].success);
+    assert!(!body[1].success);
 
-    // Now let's simulate as if we had the timestamp of the next block.
-    json.as_object_mut()
+    // The transfer of the first transaction was rolled back with the rest of the bundle
+    let balance_after = balance(
+        warp::test::request()
+            .method("GET")
+            .path(format!("/simulate-stateful/{id}/account/{recipient}").as_str())
+            .reply(&filter)
+            .await
+    );
+
+    assert_eq!(balance_after, balance_before);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/transactions").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let transactions: Vec<serde_json::Value> = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(transactions.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_snapshot_revert() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
         .unwrap()
-        .insert("blockTimestamp".into(), serde_json::json!(1702617179));
+        .stateful_simulation_id;
 
     let res = warp::test::request()
         .method("POST")
-        .path("/simulate")
-        .json(&json)
+        .path(format!("/simulate-stateful/{id}/snapshot").as_str())
         .reply(&filter)
         .await;
 
     assert_eq!(res.status(), 200);
 
-    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+    let snapshot_id = serde_json::from_slice::<StatefulSimulationSnapshotResponse>(res.body())
+        .unwrap()
+        .snapshot_id;
 
-    // Due to smart contract logic dependent on the timestamp the same transaction fails
-    // when we use the timestamp of the next block.
-    assert!(!body.success);
+    let account = "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045";
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/state").as_str())
+        .json(&serde_json::json!({ "stateOverrides": { account: { "balance": "42" } } }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/snapshot").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let later_snapshot_id = serde_json::from_slice::<StatefulSimulationSnapshotResponse>(res.body())
+        .unwrap()
+        .snapshot_id;
+
+    let transaction = |block_number: u64| {
+        serde_json::json!([{
+          "chainId": 1,
+          "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+          "to": "0x7E7d64D987cAb6EeD08A191C4C2459dAF2f8ED0B",
+          "gasLimit": 5000000,
+          "blockNumber": block_number,
+        }])
+    };
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&transaction(16968597))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/revert").as_str())
+        .json(&serde_json::json!({ "snapshotId": snapshot_id }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/account/{account}").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: StatefulSimulationAccountResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_ne!(body.balance, U256::from(42));
+
+    // The snapshot taken after the one reverted to is gone
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/revert").as_str())
+        .json(&serde_json::json!({ "snapshotId": later_snapshot_id }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    // The block env was reverted as well, so an earlier block is valid again
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&transaction(16968595))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/revert").as_str())
+        .json(&serde_json::json!({ "snapshotId": "0x99" }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "SNAPSHOT_NOT_FOUND".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_state() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/state").as_str())
+        .json(&serde_json::json!({
+          "stateOverrides": {
+            "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB": {
+              "stateDiff": {
+                "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33":
+                  "123456789012345678901234567890"
+              }
+            }
+          },
+          "blockTimestamp": 1680526200,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+          "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+          "gasLimit": 5000000,
+        }, {
+          "chainId": 1,
+          "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+          "to": "0x7E7d64D987cAb6EeD08A191C4C2459dAF2f8ED0B",
+          "gasLimit": 5000000,
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(
+        U256::from_big_endian(&body[0].return_data).as_u128(),
+        123456789012345678901234567890
+    );
+    assert_eq!(
+        U256::from(body[1].return_data.0.to_vec().as_slice()),
+        U256::from(1680526200)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_max_sessions() {
+    let config = Config {
+        max_sessions: Some(1),
+        ..replayed_config()
+    };
+    let filter = filter(config);
+
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/simulate-stateful")
+            .json(&serde_json::json!({
+                "chainId": 1,
+                "gasLimit": 5000000,
+                "blockNumber": 16968594,
+            }))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let body: StatefulSimulationResponse = serde_json::from_slice(res.body()).unwrap();
+        ids.push(body.stateful_simulation_id);
+    }
+
+    // The least recently used session was evicted to make room for the second one
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{}/snapshot", ids[0]).as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 410);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "SESSION_EXPIRED".to_string());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{}/snapshot", ids[1]).as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_max_sessions_per_api_key() {
+    let config = Config {
+        max_sessions_per_api_key: Some(1),
+        ..replayed_config()
+    };
+    let filter = warp::any()
+        .and(with_api_key("a, b"))
+        .and(simulate_routes(config, Arc::new(SharedSimulationState::default())))
+        .recover(handle_rejection);
+
+    let new_simulation = |api_key: Option<&'static str>| {
+        let request = warp::test::request()
+            .method("POST")
+            .path("/simulate-stateful")
+            .json(&serde_json::json!({
+                "chainId": 1,
+                "gasLimit": 5000000,
+                "blockNumber": 16968594,
+            }));
+        match api_key {
+            Some(api_key) => request.header("X-API-KEY", api_key),
+            None => request,
+        }
+    };
+
+    let res = new_simulation(None).reply(&filter).await;
+
+    assert_eq!(res.status(), 401);
+
+    let res = new_simulation(Some("c")).reply(&filter).await;
+
+    assert_eq!(res.status(), 401);
+
+    let mut ids = vec![];
+    for api_key in ["a", "b", "a"] {
+        let res = new_simulation(Some(api_key)).reply(&filter).await;
+
+        assert_eq!(res.status(), 200);
+
+        let body: StatefulSimulationResponse = serde_json::from_slice(res.body()).unwrap();
+        ids.push((api_key, body.stateful_simulation_id));
+    }
+
+    // Only the first session of key a was evicted, the one of key b doesn't count towards its limit
+    for ((api_key, id), status) in ids.into_iter().zip([410, 200, 200]) {
+        let res = warp::test::request()
+            .method("POST")
+            .path(format!("/simulate-stateful/{id}/snapshot").as_str())
+            .header("X-API-KEY", api_key)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), status);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_simulate_stateful_info() {
+    let config = Config {
+        admin_api_key: Some("admin".to_string()),
+        ..replayed_config()
+    };
+    let filter = filter(config);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/snapshot").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let snapshot_id = serde_json::from_slice::<StatefulSimulationSnapshotResponse>(res.body())
+        .unwrap()
+        .snapshot_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1,
+          "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+          "to": "0xdac17f958d2ee523a2206206994597c13d831ec7",
+          "gasLimit": 5000000,
+          "blockNumber": 16968595,
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: StatefulSimulationInfoResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.stateful_simulation_id, id);
+    assert_eq!(body.chain_id, 1);
+    assert_eq!(body.block_number, 16968595);
+    assert_eq!(body.transaction_count, 1);
+    assert!(body.last_used_at >= body.created_at);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulate-stateful")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 401);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/simulate-stateful")
+        .header("X-ADMIN-KEY", "admin")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<StatefulSimulationInfoResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0].stateful_simulation_id, id);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/revert").as_str())
+        .json(&serde_json::json!({ "snapshotId": snapshot_id }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: StatefulSimulationInfoResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.transaction_count, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_dump_load() {
+    let config = Config {
+        max_request_size: 1024 * 1024,
+        ..replayed_config()
+    };
+    let filter = filter(config);
+
+    let new_simulation_req = serde_json::json!({
+        "chainId": 1,
+        "gasLimit": 5000000,
+        "blockNumber": 16968594,
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&new_simulation_req)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/state").as_str())
+        .json(&serde_json::json!({
+          "stateOverrides": {
+            "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB": {
+              "stateDiff": {
+                "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33":
+                  "123456789012345678901234567890"
+              }
+            }
+          }
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    // Reads another slot of the token, which is cached but must not be dumped
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+          "data": "0x70a0823100000000000000000000000093621dca56fe26cdee86e4f6b18e116e9758ff11",
+          "gasLimit": 5000000,
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/dump").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let dump: SerializableState = serde_json::from_slice(res.body()).unwrap();
+    let address = "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB".parse().unwrap();
+
+    assert_eq!(dump.block.as_ref().unwrap().number, revm::primitives::U256::from(16968594u64));
+    assert_eq!(dump.accounts[&address].storage.len(), 1);
+
+    let mut load_req = new_simulation_req.clone();
+    load_req["state"] = serde_json::to_value(&dump).unwrap();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful/load")
+        .json(&load_req)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let loaded_id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    assert_ne!(loaded_id, id);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{loaded_id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+          "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+          "gasLimit": 5000000,
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(
+        U256::from_big_endian(&body[0].return_data).as_u128(),
+        123456789012345678901234567890
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_clone() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let set_balance = |balance: &str| serde_json::json!({
+      "stateOverrides": {
+        "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB": {
+          "stateDiff": {
+            "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33": balance
+          }
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/state").as_str())
+        .json(&set_balance("1000"))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/clone").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let clone_id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    assert_ne!(clone_id, id);
+
+    // Only the clone is changed
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{clone_id}/state").as_str())
+        .json(&set_balance("2000"))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    for (session, expected) in [(id, 1000u64), (clone_id, 2000u64)] {
+        let res = warp::test::request()
+            .method("POST")
+            .path(format!("/simulate-stateful/{session}").as_str())
+            .json(&serde_json::json!([{
+              "chainId": 1,
+              "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+              "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+              "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+              "gasLimit": 5000000,
+            }]))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+
+        assert_eq!(U256::from_big_endian(&body[0].return_data), U256::from(expected));
+    }
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{}/clone", uuid::Uuid::new_v4()).as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 404);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_session_store() {
+    let store_dir = std::env::temp_dir().join(format!("symunix-sessions-{}", uuid::Uuid::new_v4()));
+    let config = Config {
+        session_store_dir: Some(store_dir.to_string_lossy().to_string()),
+        ..replayed_config()
+    };
+
+    let state = Arc::new(SharedSimulationState::new(&config));
+    let filter = warp::any()
+        .and(simulate_routes(config.clone(), Arc::clone(&state)))
+        .recover(handle_rejection);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/state").as_str())
+        .json(&serde_json::json!({
+          "stateOverrides": {
+            "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB": {
+              "stateDiff": {
+                "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33":
+                  "123456789012345678901234567890"
+              }
+            }
+          }
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    // Sessions are written in the background, wait for the override to be saved
+    let path = store_dir.join(format!("{id}.json"));
+    let token = "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB".parse().unwrap();
+    let mut saved = false;
+    for _ in 0..50 {
+        saved = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<PersistedSession>(&bytes).ok())
+            .map_or(false, |session| session.state.accounts.contains_key(&token));
+        if saved {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(saved);
+
+    // A new server with the same store picks the session up
+    let restored_state = Arc::new(SharedSimulationState::new(&config));
+    restored_state.restore_sessions(&config).await;
+    let filter = warp::any()
+        .and(simulate_routes(config.clone(), Arc::clone(&restored_state)))
+        .recover(handle_rejection);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+          "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+          "gasLimit": 5000000,
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(
+        U256::from_big_endian(&body[0].return_data).as_u128(),
+        123456789012345678901234567890
+    );
+
+    let res = warp::test::request()
+        .method("DELETE")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    assert!(!store_dir.join(format!("{id}.json")).exists());
+
+    std::fs::remove_dir_all(store_dir).ok();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_simulate_stateful_reads() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let token = "0xdef1ca1fb7fbcdc777520aa7f396b4e015f497ab";
+    let slot = "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33";
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/state").as_str())
+        .json(&serde_json::json!({
+          "stateOverrides": {
+            token: { "stateDiff": { slot: "1000" } },
+            "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045": { "balance": "42" }
+          }
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/account/0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: StatefulSimulationAccountResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.balance, U256::from(42));
+    assert!(body.code.is_empty());
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/account/{token}").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: StatefulSimulationAccountResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(!body.code.is_empty());
+
+    let storage_path = format!("/simulate-stateful/{id}/storage/{token}/{slot}");
+    let res = warp::test::request()
+        .method("GET")
+        .path(storage_path.as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: StatefulSimulationStorageResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(U256::from_big_endian(body.value.as_bytes()), U256::from(1000));
+
+    // Overrides of a call are dropped afterwards
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/call").as_str())
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": token,
+          "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+          "gasLimit": 5000000,
+          "stateOverrides": {
+            token: { "stateDiff": { slot: "2000" } }
+          }
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(U256::from_big_endian(&body.return_data), U256::from(2000));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(storage_path.as_str())
+        .reply(&filter)
+        .await;
+
+    let body: StatefulSimulationStorageResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(U256::from_big_endian(body.value.as_bytes()), U256::from(1000));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_rpc() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let recipient = "0x1111111111111111111111111111111111111111";
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/rpc/{id}").as_str())
+        .json(&serde_json::json!([
+          { "jsonrpc": "2.0", "id": 1, "method": "eth_chainId", "params": [] },
+          { "jsonrpc": "2.0", "id": 2, "method": "eth_blockNumber", "params": [] },
+          { "jsonrpc": "2.0", "id": 3, "method": "eth_sendTransaction", "params": [{
+            "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+            "to": recipient,
+            "value": "0x3e8",
+          }] },
+          { "jsonrpc": "2.0", "id": 4, "method": "eth_getBalance", "params": [recipient, "latest"] },
+          { "jsonrpc": "2.0", "id": 5, "method": "eth_getBalance", "params": [recipient, "0x1"] },
+          { "jsonrpc": "2.0", "id": 6, "method": "eth_foo", "params": [] },
+        ]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<JsonRpcResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body[0].result, Some(serde_json::json!("0x1")));
+    assert_eq!(body[1].result, Some(serde_json::json!("0x102eb92")));
+    assert!(body[2].error.is_none());
+    assert_eq!(body[3].result, Some(serde_json::json!("0x3e8")));
+    assert!(body[4].error.is_some());
+    assert_eq!(body[5].error.as_ref().unwrap().code, -32601);
+
+    let hash = body[2].result.clone().unwrap();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/rpc/{id}").as_str())
+        .json(&serde_json::json!({
+          "jsonrpc": "2.0",
+          "id": 7,
+          "method": "eth_getTransactionReceipt",
+          "params": [hash],
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: JsonRpcResponse = serde_json::from_slice(res.body()).unwrap();
+    let receipt = body.result.unwrap();
+
+    assert_eq!(body.id, serde_json::json!(7));
+    assert_eq!(receipt["status"], serde_json::json!("0x1"));
+    assert_eq!(receipt["transactionHash"], hash);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/rpc/{id}").as_str())
+        .json(&serde_json::json!({
+          "jsonrpc": "2.0",
+          "id": 8,
+          "method": "eth_sendTransaction",
+          "params": [{
+            "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+            "to": recipient,
+            "value": "0x3e8",
+            "nonce": "0x0",
+          }],
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: JsonRpcResponse = serde_json::from_slice(res.body()).unwrap();
+    let error = body.error.unwrap();
+
+    assert_eq!(error.code, -32000);
+    assert!(error.message.starts_with("nonce too low"));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/rpc/{id}").as_str())
+        .json(&serde_json::json!({
+          "jsonrpc": "2.0",
+          "id": 9,
+          "method": "eth_call",
+          "params": [{
+            "to": "0xdAC17F958D2ee523a2206206994597C13D831ec7",
+            "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+          }, "latest"],
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: JsonRpcResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body.result.unwrap().as_str().unwrap().starts_with("0x"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_rpc_cheats() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+            "blockTimestamp": 1680526200,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let account = "0x1111111111111111111111111111111111111111";
+    let token = "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB";
+    let slot = "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33";
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/rpc/{id}").as_str())
+        .json(&serde_json::json!([
+          { "jsonrpc": "2.0", "id": 1, "method": "evm_snapshot", "params": [] },
+          { "jsonrpc": "2.0", "id": 2, "method": "anvil_setBalance", "params": [account, "0x2a"] },
+          { "jsonrpc": "2.0", "id": 3, "method": "hardhat_setNonce", "params": [account, "0x5"] },
+          { "jsonrpc": "2.0", "id": 4, "method": "anvil_setStorageAt", "params": [
+            token,
+            slot,
+            "0x00000000000000000000000000000000000000000000000000000000000003e8"
+          ] },
+          { "jsonrpc": "2.0", "id": 5, "method": "evm_increaseTime", "params": [60] },
+          { "jsonrpc": "2.0", "id": 6, "method": "evm_mine", "params": [] },
+          { "jsonrpc": "2.0", "id": 7, "method": "eth_getBalance", "params": [account, "latest"] },
+          { "jsonrpc": "2.0", "id": 8, "method": "eth_getTransactionCount", "params": [account, "latest"] },
+          { "jsonrpc": "2.0", "id": 9, "method": "eth_getStorageAt", "params": [token, slot, "latest"] },
+          { "jsonrpc": "2.0", "id": 10, "method": "eth_blockNumber", "params": [] },
+          { "jsonrpc": "2.0", "id": 11, "method": "anvil_impersonateAccount", "params": [account] },
+          { "jsonrpc": "2.0", "id": 12, "method": "evm_increaseTime", "params": ["0x1e"] },
+        ]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<JsonRpcResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body.iter().all(|response| response.error.is_none()));
+    assert_eq!(body[6].result, Some(serde_json::json!("0x2a")));
+    assert_eq!(body[7].result, Some(serde_json::json!("0x5")));
+    assert_eq!(
+        body[8].result,
+        Some(serde_json::json!("0x00000000000000000000000000000000000000000000000000000000000003e8"))
+    );
+    assert_eq!(body[9].result, Some(serde_json::json!("0x102eb93")));
+    // evm_increaseTime returns the total time added
+    assert_eq!(body[4].result, Some(serde_json::json!("0x3c")));
+    assert_eq!(body[11].result, Some(serde_json::json!("0x5a")));
+
+    let snapshot_id = body[0].result.clone().unwrap();
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/rpc/{id}").as_str())
+        .json(&serde_json::json!([
+          { "jsonrpc": "2.0", "id": 1, "method": "evm_revert", "params": [snapshot_id] },
+          { "jsonrpc": "2.0", "id": 2, "method": "eth_getBalance", "params": [account, "latest"] },
+          { "jsonrpc": "2.0", "id": 3, "method": "eth_blockNumber", "params": [] },
+          { "jsonrpc": "2.0", "id": 4, "method": "evm_revert", "params": [snapshot_id] },
+        ]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<JsonRpcResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body[0].result, Some(serde_json::json!(true)));
+    assert_eq!(body[1].result, Some(serde_json::json!("0x0")));
+    assert_eq!(body[2].result, Some(serde_json::json!("0x102eb92")));
+    // Like anvil, a snapshot can only be reverted to once
+    assert_eq!(body[3].result, Some(serde_json::json!(false)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_mine() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    // Token transfers, they emit a Transfer log
+    let transfer = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+      "data": "0xa9059cbb000000000000000000000000111111111111111111111111111111111111111100000000000000000000000000000000000000000000000000000000000003e8",
+      "gasLimit": 500000,
+    });
+    let mut funded_transfer = transfer.clone();
+    funded_transfer["stateOverrides"] = serde_json::json!({
+      "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB": {
+        "stateDiff": {
+          "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33": "1000000"
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([funded_transfer, transfer]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body.iter().all(|response| response.success));
+    assert_ne!(body[0].transaction_hash, body[1].transaction_hash);
+    assert_eq!(body[1].logs[0].transaction_hash, body[1].transaction_hash);
+    assert_eq!(body[1].logs[0].transaction_index, Some(1.into()));
+    assert_eq!(body[1].logs[0].log_index, Some(1.into()));
+    assert_eq!(body[1].logs[0].block_number, Some(16968594.into()));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/mine").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let block: ethers::types::Block<ethers::types::H256> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(block.number, Some(16968594.into()));
+    assert_eq!(
+        block.transactions,
+        body.iter().map(|response| response.transaction_hash.unwrap()).collect::<Vec<_>>()
+    );
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/transactions").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let transactions: Vec<serde_json::Value> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[1]["receipt"]["blockHash"], serde_json::json!(block.hash.unwrap()));
+    assert_eq!(transactions[1]["receipt"]["logs"][0]["blockHash"], serde_json::json!(block.hash.unwrap()));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/rpc/{id}").as_str())
+        .json(&serde_json::json!({
+          "jsonrpc": "2.0", "id": 1, "method": "eth_blockNumber", "params": []
+        }))
+        .reply(&filter)
+        .await;
+
+    let body: JsonRpcResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.result, Some(serde_json::json!("0x102eb93")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_logs() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+          "data": "0xa9059cbb000000000000000000000000111111111111111111111111111111111111111100000000000000000000000000000000000000000000000000000000000003e8",
+          "gasLimit": 500000,
+          "stateOverrides": {
+            "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB": {
+              "stateDiff": {
+                "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33": "1000000"
+              }
+            }
+          }
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+    let transaction_hash = body[0].transaction_hash;
+
+    // Transfer(address,address,uint256) logs of the token sent to 0x1111...
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/logs").as_str())
+        .json(&serde_json::json!({
+          "address": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+          "topics": [
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef",
+            null,
+            "0x0000000000000000000000001111111111111111111111111111111111111111"
+          ],
+          "fromBlock": "latest",
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let logs: Vec<ethers::types::Log> = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].transaction_hash, transaction_hash);
+
+    // Logs before the fork point come from the forked chain
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/rpc/{id}").as_str())
+        .json(&serde_json::json!({
+          "jsonrpc": "2.0",
+          "id": 1,
+          "method": "eth_getLogs",
+          "params": [{
+            "address": "0xdAC17F958D2ee523a2206206994597C13D831ec7",
+            "fromBlock": "0x102eb91",
+            "toBlock": "0x102eb91",
+          }],
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: JsonRpcResponse = serde_json::from_slice(res.body()).unwrap();
+    let logs: Vec<ethers::types::Log> = serde_json::from_value(body.result.unwrap()).unwrap();
+
+    assert!(!logs.is_empty());
+    assert!(logs.iter().all(|log| log.block_number == Some(16968593.into())));
+
+    // The fork block holds the transactions of the simulation, not the ones of the forked chain
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/logs").as_str())
+        .json(&serde_json::json!({
+          "address": "0xdAC17F958D2ee523a2206206994597C13D831ec7",
+          "fromBlock": "0x102eb92",
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let logs: Vec<ethers::types::Log> = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(logs.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_roll_fork() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+          "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+          "gasLimit": 5000000,
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/state").as_str())
+        .json(&serde_json::json!({
+          "stateOverrides": {
+            "0x1111111111111111111111111111111111111111": { "balance": "42" }
+          }
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/roll-fork").as_str())
+        .json(&serde_json::json!({ "blockNumber": 16968600 }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .reply(&filter)
+        .await;
+
+    let body: StatefulSimulationInfoResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.fork_block_number, 16968600);
+    assert_eq!(body.block_number, 16968600);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/account/0x1111111111111111111111111111111111111111").as_str())
+        .reply(&filter)
+        .await;
+
+    let body: StatefulSimulationAccountResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.balance, U256::from(42));
+
+    // The transactions of the simulation move along with the fork block
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/transactions").as_str())
+        .reply(&filter)
+        .await;
+
+    let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body[0]["receipt"]["blockNumber"], "0x102eb98");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}/roll-fork").as_str())
+        .json(&serde_json::json!({ "blockNumber": 16968594 }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_multi_fork() {
+    let filter = filter(replayed_config());
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 5000000,
+            "blockNumber": 16968594,
+            "forks": [{ "chainId": 137, "blockNumber": 45000000 }],
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1,
+          "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+          "to": "0xdac17f958d2ee523a2206206994597c13d831ec7",
+          "data": "0x095ea7b300000000000000000000000060f727bdead2ce49b00f2a2133fc707b931d130b0000000000000000000000000000000000000000000000000000000000989680",
+          "gasLimit": 5000000,
+          "blockNumber": 16968595,
+        }, {
+          "chainId": 137,
+          "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+          "to": "0x2791Bca1f2de4661ED88A30C99A7a9449Aa84174",
+          "data": "0x313ce567",
+          "gasLimit": 5000000,
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: MultiChainSimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(body.gas_used, body.results.iter().map(|result| result.simulation.gas_used).sum());
+    assert_eq!(body.results.len(), 2);
+    assert_eq!(body.results[0].chain_id, 1);
+    assert_eq!(body.results[0].simulation.block_number, 16968595);
+    assert_eq!(body.results[1].chain_id, 137);
+    assert_eq!(body.results[1].simulation.block_number, 45000000);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .reply(&filter)
+        .await;
+
+    let body: StatefulSimulationInfoResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.chain_id, 1);
+    assert_eq!(body.block_number, 16968595);
+
+    // Chains the session was not created with are rejected
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 10,
+          "from": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+          "to": "0x93621dca56fe26cdee86e4f6b18e116e9758ff11",
+          "gasLimit": 5000000,
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "INCORRECT_CHAIN_ID".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_time_sensitive_tx() {
+    let config = Config {
+        max_request_size: 64 * 1024,
+        ..replayed_config()
+    };
+    let filter = filter(config);
+
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0x16c473448e770ff647c69cbe19e28528877fba1b",
+      "to": "0x9008d19f58aabd9ed0d60971565aa8510560ab41",
+      "data": "0x13d79a0b000000000000000000000000000000000000000000000000000000000000008000000000000000000000000000000000000000000000000000000000000001c000000000000000000000000000000000000000000000000000000000000003000000000000000000000000000000000000000000000000000000000000000f80000000000000000000000000000000000000000000000000000000000000000900000000000000000000000063b420fb3294ba1d300ce5d3ba4bbca0f4fe5e3b0000000000000000000000006602e9319f2c5ec0ba31ffcdc4301d7ef03b709e000000000000000000000000826180541412d574cf1336d22c0c0a287822678a000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f000000000000000000000000eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee000000000000000000000000826180541412d574cf1336d22c0c0a287822678a000000000000000000000000d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f0000000000000000000000000000000000000000000000000000000000000009000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000b03be4f33de3117a20000000000000000000000000000000000000000000001dbdaf552b2e610f9620000000000000000000000000000000000004aaa0d413df15c88a41958d3500500000000000000000000000000000000000000000002d6d05933e23134fa4940000000000000000000000000000000000000000000000001a70d5fdd2071474f00000000000000000000000000000000000000000002d6d05933e23134fa4940000000000000000000000000000000000000000000003c97e632500fe652a7f400000000000000000000000000000000000000000000003635c9adc5dea0000000000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000000026000000000000000000000000000000000000000000000000000000000000008c00000000000000000000000000000000000000000000000000000000000000a8000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000d0dfd95ccd6e070eda7e153a54fb67f0628ba26c00000000000000000000000000000000000000000000281af54148361a9400000000000000000000000000000000000000000000000000000c20964c235c076b00000000000000000000000000000000000000000000000000000000657c0368e9f29ae547955463ed535162aefee525d8d309571a2b18bc26086c8c35d781eb000000000000000000000000000000000000000000000020f66db0a5202c0000000000000000000000000000000000000000000000000000000000000000000800000000000000000000000000000000000000000000281af54148361a9400000000000000000000000000000000000000000000000000000000000000000160000000000000000000000000000000000000000000000000000000000000004176b5974825abcbf26f0e2312e950444ca46758a8e41df942369d5b1395c630b40e1b1e728d4589f16fff33287cfae3922c81e004bef6207d1a733f51a8da45741c00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000700000000000000000000000000000000000000000000000000000000000000080000000000000000000000006aeef86bb8742c6739639634f64ec8d98d6b835800000000000000000000000000000000000000000000003635c9adc5dea00000000000000000000000000000000000000000000000003af311dd167044bb7b3800000000000000000000000000000000000000000000000000000000657bf182351173d8df3ae67ecd9202256c01a4ccbaf71234af377033ba8e9c53af6835460000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004000000000000000000000000000000000000000000000003635c9adc5dea00000000000000000000000000000000000000000000000000000000000000000016000000000000000000000000000000000000000000000000000000000000004d86aeef86bb8742c6739639634f64ec8d98d6b83585fd7e97dc078f884a2676e1345748b1feace7b0abee5d00ecadb6e574dcdd109a63e8943d5a25ba2e97094ad7d83dc28a6572da797d6b3e7fc6663bd93efb789fc17e489000000000000000000000000000000000000000000000000000000000000008000000000000000000000000000000000000000000000000000000000000002200000000000000000000000000000000000000000000000000000000000000180000000000000000000000000826180541412d574cf1336d22c0c0a287822678a000000000000000000000000d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f0000000000000000000000006aeef86bb8742c6739639634f64ec8d98d6b835800000000000000000000000000000000000000000000003635c9adc5dea00000000000000000000000000000000000000000000000003af311dd167044bb7b3800000000000000000000000000000000000000000000000000000000657bf182351173d8df3ae67ecd9202256c01a4ccbaf71234af377033ba8e9c53af6835460000000000000000000000000000000000000000000000000000000000000000f3b277728b3fee749481eb3e0b3b48980dbbab78658fc419025cb16eee34677500000000000000000000000000000000000000000000000000000000000000005a28e9363bb942b639270062aa6bb295f434bcdfc42c97267bf003f272060dc95a28e9363bb942b639270062aa6bb295f434bcdfc42c97267bf003f272060dc90000000000000000000000000000000000000000000000000000000000000280000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000000024000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006cf1e9ca41f7611def408122793c358a3d11e5a500000000000000000000000000000000000000000000000000000018c6c0264000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000140000000000000000000000000826180541412d574cf1336d22c0c0a287822678a000000000000000000000000d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f0000000000000000000000006aeef86bb8742c6739639634f64ec8d98d6b835800000000000000000000000000000000000000000000003635c9adc5dea00000000000000000000000000000000000000000000000003af311dd167044bb7b380000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000000000000000000000000000000000004b00000000000000000000000000000000000000000000000000000000000000000351173d8df3ae67ecd9202256c01a4ccbaf71234af377033ba8e9c53af68354600000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000400000000000000000000000000000000000000000000000000000000000000030000000000000000000000002f45724d7e384b38d5c97206e78470544304887f000000000000000000000000000000000000000000000000989cd478134caef300000000000000000000000000000000000000000000000000000005b06a600d00000000000000000000000000000000000000000000000000000000ffffffff15f7794a143d0e5d3217190e3baeac63e6f76c816b10b9a6b89fcb915164d281000000000000000000000000000000000000000000000000000b05401dff510d0000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000989cd478134caef30000000000000000000000000000000000000000000000000000000000000160000000000000000000000000000000000000000000000000000000000000002840a50cf069e992aa4536211b23f286ef8875218740a50cf069e992aa4536211b23f286ef88752187000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000030000000000000000000000009afd9f0c276b40411a4ca6a53dfa7e3d832cd66f0000000000000000000000000000000000000000000008f63ce03c890736f2a0000000000000000000000000000000000000000000000000000000013e4ab2da00000000000000000000000000000000000000000000000000000000657bf3f8db47033948acea8674bb2501bc6d2908d3cdf86eb9262a8585466e877e4ecd89000000000000000000000000000000000000000000000002ca36c150997d600000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000008f63ce03c890736f2a000000000000000000000000000000000000000000000000000000000000001600000000000000000000000000000000000000000000000000000000000000041da7fefd12133c5dd85ac4ca604a517de4dcf526d7fb9e363ad8eb4c9b4f2e57d0e5b72ca03e2e2ec68d7486060c42802e01d42b7366fc64c6bdbd751cae6624e1b000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000014000000000000000000000000000000000000000000000000000000000000022e00000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000002000000000000000000000000040a50cf069e992aa4536211b23f286ef887521870000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000044c84c1c8000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000008a00000000000000000000000000000000000000000000000000000000000000a400000000000000000000000000000000000000000000000000000000000000ca00000000000000000000000000000000000000000000000000000000000000e400000000000000000000000000000000000000000000000000000000000000f60000000000000000000000000000000000000000000000000000000000000102000000000000000000000000000000000000000000000000000000000000014c00000000000000000000000000000000000000000000000000000000000001580000000000000000000000000000000000000000000000000000000000000164000000000000000000000000000000000000000000000000000000000000018a000000000000000000000000000000000000000000000000000000000000019600000000000000000000000000000000000000000000000000000000000001b200000000000000000000000000000000000000000000000000000000000001c800000000000000000000000000000000000000000000000000000000000001ee000000000000000000000000000000000000000000000000000000000000020c0000000000000000000000000beb09000fa59627dc02bb55448ac1893eaa501a500000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000060424a305f20000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000046000000000000000000000000000000000000000000000000000000000000004c000000000000000000000000000000000000000000000000000000000657beebe0000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab410000000000000000000000000000000000000000000000000000000000000140000000000000000000000000000000000000000000000000000000000000018000000000000000000000000000000000000000000000000000000000000001c0000000000000000000000000000000000000000000000000000000000000024000000000000000000000000000000000000000000000000000000000000002c000000000000000000000000000000000000000000000000000000000000003400000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000003c0000000000000000000000000000000000000000000000000000000000000000100000000000000000000000051c72848c68a965f66fa7a88855f9f7784502a7f0000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000003187e072c1b000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000001000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000001000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000989cd478134caef300000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000005cd98afdf0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000004000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000004000000000000000000000000000000000000000000000000000000000000000418fba48f1c1f9d272578cfc682e24d59bbbf341683dced64b5088822638e46a344dd1c2aa35d76c624fcd6720eeb1bb79bfe1a2b8e9838f31de532c438ed7d6461b0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e592427a0aece92de3edee1f18e0157c05861564000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000104414bf3890000000000000000000000006602e9319f2c5ec0ba31ffcdc4301d7ef03b709e000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc200000000000000000000000000000000000000000000000000000000000027100000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000f00000000000000000000000000000000000000000000000000000008f63ce03c890736f27a00000000000000000000000000000000000000000000000022b0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ba12222222228d8ba445958a75a0704d566bf2c80000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001c452bbbe2900000000000000000000000000000000000000000000000000000000000000e00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006e0000000000000000000000000000000000000000000000000000000000f0000000000af15e6cbe19e30be827f27069cf010b57ce9f3ae0002000000000000000004d30000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800000000000000000000000000000000000000000000000000b3cea2601d204400000000000000000000000000000000000000000000000000000000000000c00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001b81d678ffb9c0263b24a97847620c99d213eb14000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000104414bf389000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800000000000000000000000000000000000000000000000000000000000001f40000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000f00000000000000000000000000000000000000000000000000000000000c65e538006d384d0000000000000000000000000000000000000000000000000000000078b000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000005426178799ee0a0181a89b4f57efddfab49941ec0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000845b41b90800000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000042daef9a502b9790000000000000000000000000000000000000000000000000000000028b000000000000000000000000000000000000000000000000000000000000000000000000000000000000008775ccb6674d6bdceb0797c364c2653ed84f38400000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000002438e9922e000000000000000000000000000000000000000000000000000221b262dd800000000000000000000000000000000000000000000000000000000000000000000000000000000000ba12222222228d8ba445958a75a0704d566bf2c8000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000404945bcec90000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000012000000000000000000000000000000000000000000000000000000000000003000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab410000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000038000000000000000000000000000000000000000000000000000000f000000000000000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000010008775ccb6674d6bdceb0797c364c2653ed84f3840002000000000000000004f0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000bdf1d19bac30b5800000000000000000000000000000000000000000000000000000000000000a0000000000000000000000000000000000000000000000000000000000000000079c58f70905f734641735bc61e45c19dd9ad60bc0000000000000000000004e700000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000003000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc200000000000000000000000079c58f70905f734641735bc61e45c19dd9ad60bc000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800000000000000000000000000000000000000000000000000000000000000030000000000000000000000000000000000000000000000000bdf1d19bac30b580000000000000000000000000000000000000000000000000000000000000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffff8bf000000000000000000000000000000000000000000000000000000000000000000000000000000000000008775ccb6674d6bdceb0797c364c2653ed84f38400000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000002438e9922e000000000000000000000000000000000000000000000000000aa87bee5380000000000000000000000000000000000000000000000000000000000000000000000000000000000096646936b91d6b9d7d0c47c496afbf3d6ec7b6f800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000002438e9922e000000000000000000000000000000000000000000000000000221b262dd800000000000000000000000000000000000000000000000000000000000000000000000000000000000ba12222222228d8ba445958a75a0704d566bf2c80000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001c452bbbe2900000000000000000000000000000000000000000000000000000000000000e00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab410000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000d70000000000000000000000000000000000000000000000000000000000f000000000096646936b91d6b9d7d0c47c496afbf3d6ec7b6f80002000000000000000000190000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb4800000000000000000000000000000000000000000000000001618e2c8343630c00000000000000000000000000000000000000000000000000000000000000c000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000096646936b91d6b9d7d0c47c496afbf3d6ec7b6f800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000060000000000000000000000000000000000000000000000000000000000000002438e9922e000000000000000000000000000000000000000000000000000aa87bee53800000000000000000000000000000000000000000000000000000000000000000000000000000000000bbf1ee38152e9d8e3470dc47947eaa65dca94913000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000124c04b8d59000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000f000000000000000000000000000000000000000000000000000000000000b14d25fe99ce1e0000000000000000000000000000000000000000000000000000000006b00000000000000000000000000000000000000000000000000000000000000000003cc02aaa39b223fe8d0a0e5c4f27ead9083c756cc211a653ddfbb61e0feff5484919f06d9d254bf65fa0b86991c6218b36c1d19d4a2e9eb0ce3606eb480000000000000000000000000000000000000000000000000000000000000000000000000000000000000000831bf48183b999fde45294b14b55199072f0801b0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000c475d39ecb0000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000377936629f03f34000000000000000000000000fffd8963efd1fc6a506488495d951d5263988d250000000000000000000000000000000000000000000000000000000021c0000000000000000000000000000000000000000000000000000000000f000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ba12222222228d8ba445958a75a0704d566bf2c80000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001c452bbbe2900000000000000000000000000000000000000000000000000000000000000e00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e2000000000000000000000000000000000000000000000000000000000000000000f000000000040f1cb91325dd8ef24dfac085c9ba46ea309e5e2000200000000000000000644000000000000000000000000000000000000000000000000000000000000000000000000000000000000000063b420fb3294ba1d300ce5d3ba4bbca0f4fe5e3b000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc200000000000000000000000000000000000000000000281af54148361a94000000000000000000000000000000000000000000000000000000000000000000c0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e592427a0aece92de3edee1f18e0157c05861564000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000144c04b8d59000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000009008d19f58aabd9ed0d60971565aa8510560ab4100000000000000000000000000000000000000000000000000000f0000000000000000000000000000000000000000000000000000000035dea0e8d91c9800f9000000000000000000000000000000000000000000003c9000000000000000000000000000000000000000000000000000000000000000000000000000000042826180541412d574cf1336d22c0c0a287822678a000bb8c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2000bb8d7c1eb0fe4a30d3b2a846c04aa6300888f087a5f00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc20000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000000242e1a7d4d0000000000000000000000000000000000000000000000000e204038c7e0f68e00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000007c70ae",
+      "gasLimit": 5000000,
+      "value": "0",
+      "blockNumber": 18789653,
+      // happened at timestamp 1702617167
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body.success);
+
+    // Now let's simulate as if we had the timestamp of the next block.
+    json.as_object_mut()
+        .unwrap()
+        .insert("blockTimestamp".into(), serde_json::json!(1702617179));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    // Due to smart contract logic dependent on the timestamp the same transaction fails
+    // when we use the timestamp of the next block.
+    assert!(!body.success);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_offline() {
+    let genesis = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/genesis.json");
+    let offline_state = OfflineState::load_file(genesis).unwrap();
+    let filter = filter(Config { offline_state: Some(Arc::new(offline_state)), ..config() });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&serde_json::json!({
+          "chainId": 1337,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x1000000000000000000000000000000000000001",
+          "gasLimit": 100000,
+          "value": "0"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["X-Upstream"], "offline");
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body.success);
+    assert_eq!(body.block_number, 1);
+    assert_eq!(U256::from_big_endian(&body.return_data), U256::from(42));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&serde_json::json!({
+          "chainId": 1,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x1000000000000000000000000000000000000001",
+          "gasLimit": 100000,
+          "value": "0"
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 400);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "CHAIN_ID_NOT_SUPPORTED".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_offline() {
+    let genesis = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/genesis.json");
+    let offline_state = OfflineState::load_file(genesis).unwrap();
+    let filter = filter(Config { offline_state: Some(Arc::new(offline_state)), ..config() });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1337,
+            "gasLimit": 5000000,
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path(format!("/simulate-stateful/{id}").as_str())
+        .json(&serde_json::json!([{
+          "chainId": 1337,
+          "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+          "to": "0x1111111111111111111111111111111111111111",
+          "gasLimit": 21000,
+          "value": "1000"
+        }]))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: Vec<SimulationResponse> = serde_json::from_slice(res.body()).unwrap();
+
+    assert!(body[0].success);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path(format!("/simulate-stateful/{id}/account/0x1111111111111111111111111111111111111111").as_str())
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: StatefulSimulationAccountResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.balance, U256::from(1000));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "records from a live upstream"]
+async fn post_simulate_rpc_cassette() {
+    let path = std::env::temp_dir().join("symunix-test-cassette.json");
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let recorded = {
+        let cassette = Cassette::load(path, CassetteMode::Record).unwrap();
+        let filter = filter(Config { rpc_cassette: Some(Arc::new(cassette)), ..config() });
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/simulate")
+            .json(&json)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        serde_json::from_slice::<SimulationResponse>(res.body()).unwrap()
+    };
+
+    // Replaying needs no upstream at all
+    let mut chains = ChainRegistry::builtin();
+    chains.set_rpc_urls(1, vec!["http://127.0.0.1:1".to_string()]);
+    let cassette = Cassette::load(path, CassetteMode::Replay).unwrap();
+    let filter = filter(Config {
+        chains: Arc::new(chains),
+        rpc_cassette: Some(Arc::new(cassette)),
+        ..config()
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.gas_used, recorded.gas_used);
+    assert_eq!(body.block_number, recorded.block_number);
+    assert_eq!(body.return_data, recorded.return_data);
+
+    let mut json = json.clone();
+    json["blockNumber"] = serde_json::json!(16784601);
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 502);
+
+    let body: ErrorMessage = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.message, "UPSTREAM_NOT_RECORDED".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_pooled_backend() {
+    let config = replayed_config();
+    // Forks are not pooled while recording, the requests of the test are still recorded
+    let pooling = config.rpc_cassette.as_ref().unwrap().mode == CassetteMode::Replay;
+    let backend_pool = Arc::clone(&config.backend_pool);
+    let filter = filter(config);
+    let warm_fork = || {
+        let forks = backend_pool.forks.lock().unwrap();
+        assert_eq!(forks.len(), 1);
+        forks.values().next().map(|pooled| Arc::clone(&pooled.fork)).unwrap()
+    };
+
+    let mut json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB",
+      "data": "0x70a08231000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+      "gasLimit": 5000000,
+      "blockNumber": 16784600,
+      "stateOverrides": {
+        "0xDEf1CA1fb7FBcDC777520aa7f396b4E015F497aB": {
+          "stateDiff": {
+            "0xfca351f4d96129454cfc8ef7930b638ac71fea35eb69ee3b8d959496beb04a33":
+              "123456789012345678901234567890"
+          }
+        }
+      }
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(U256::from_big_endian(&body.return_data).as_u128(), 123456789012345678901234567890);
+    let first_fork = pooling.then(warm_fork);
+
+    // The second simulation starts from the same warm fork, without the overrides of the first
+    json.as_object_mut().unwrap().remove("stateOverrides");
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate")
+        .json(&json)
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let body: SimulationResponse = serde_json::from_slice(res.body()).unwrap();
+
+    assert_eq!(body.block_number, 16784600);
+    assert_ne!(U256::from_big_endian(&body.return_data).as_u128(), 123456789012345678901234567890);
+    if let Some(first_fork) = first_fork {
+        assert!(Arc::ptr_eq(&first_fork, &warm_fork()));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_queue_full() {
+    let config = Config {
+        simulation_workers: Arc::new(SimulationWorkers::new(1, Some(0))),
+        ..replayed_config()
+    };
+    let filter = filter(config);
+
+    let json = serde_json::json!({
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000",
+      "blockNumber": 16784600
+    });
+
+    let simulate = || warp::test::request().method("POST").path("/simulate").json(&json).reply(&filter);
+    let (first, second, status) = tokio::join!(
+        simulate(),
+        simulate(),
+        warp::test::request().method("GET").path("/status").reply(&filter)
+    );
+
+    // The only worker runs one simulation and there is no room to wait for it
+    let mut statuses = vec![first.status().as_u16(), second.status().as_u16()];
+    statuses.sort();
+    assert_eq!(statuses, vec![200, 503]);
+    assert_eq!(status.status(), 200);
+
+    let failed = if first.status() == 503 { first } else { second };
+    let body: ErrorMessage = serde_json::from_slice(failed.body()).unwrap();
+
+    assert_eq!(body.message, "SIMULATION_QUEUE_FULL".to_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn post_simulate_stateful_session_queue() {
+    let config = Config {
+        simulation_workers: Arc::new(SimulationWorkers::new(1, Some(0))),
+        ..replayed_config()
+    };
+    let filter = filter(config);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/simulate-stateful")
+        .json(&serde_json::json!({
+            "chainId": 1,
+            "gasLimit": 500000,
+            "blockNumber": 16784600
+        }))
+        .reply(&filter)
+        .await;
+
+    assert_eq!(res.status(), 200);
+
+    let id = serde_json::from_slice::<StatefulSimulationResponse>(res.body())
+        .unwrap()
+        .stateful_simulation_id;
+
+    let json = serde_json::json!([{
+      "chainId": 1,
+      "from": "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045",
+      "to": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+      "gasLimit": 21000,
+      "value": "100000"
+    }]);
+    let path = format!("/simulate-stateful/{id}");
+    let simulate = || warp::test::request().method("POST").path(&path).json(&json).reply(&filter);
+    let (first, second) = tokio::join!(simulate(), simulate());
+
+    // The second request waits for the first on the simulation, not in the worker queue
+    assert_eq!(first.status(), 200);
+    assert_eq!(second.status(), 200);
 }